@@ -1,87 +1,370 @@
 use crate::scanner::{Token, TokenType};
 use crate::scanner;
-use crate::environment::Environment;
+use crate::callable::Callable;
+use crate::class::LoxInstance;
+use crate::error::RuntimeError;
+use crate::interpreter::Interpreter;
+use crate::iter::{LoxIterator, LoxRange};
+use crate::limits;
+use crate::map::LoxMap;
+use crate::numeric;
+use crate::span::Span;
+use crate::userdata::{self, UserData};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum LiteralValue {
-    Number(f32),
+    Int(i64),
+    /// An integer outside the `i64` range; smaller results are always `Int`.
+    BigInt(Rc<BigInt>),
+    /// An exact fraction whose denominator is never 1.
+    Rational(Rc<BigRational>),
+    Float(f64),
     StringValue(String),
     True,
     False,
     Nil,
+    Callable(Callable),
+    Instance(LoxInstance),
+    /// Clones share the same elements, so a list passed to a function can be
+    /// modified by it.
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    /// Shared like `List`; keys are restricted to hashable values.
+    Map(Rc<RefCell<LoxMap>>),
+    Range(LoxRange),
+    /// A partially consumed iterator, e.g. one returned by a native function.
+    Iterator(Rc<RefCell<dyn LoxIterator>>),
+    /// An object owned by the host program; see `UserData`.
+    UserData(Rc<RefCell<dyn UserData>>),
 }
 
 
-fn unwrap_as_f32(literal: Option<scanner::LiteralValue>) -> f32 {
+fn unwrap_as_number(literal: Option<scanner::LiteralValue>) -> Option<LiteralValue> {
     match literal {
-        Some(scanner::LiteralValue::IntValue(x)) => x as f32,
-        Some(scanner::LiteralValue::FloatValue(x)) => x as f32,
-        _ => panic!("Could not unwrap as f32"),
+        Some(scanner::LiteralValue::IntValue(x)) => Some(LiteralValue::Int(x)),
+        Some(scanner::LiteralValue::BigIntValue(x)) => Some(numeric::big(x)),
+        Some(scanner::LiteralValue::FloatValue(x)) => Some(LiteralValue::Float(x)),
+        _ => None,
     }
 }
 
-fn unwrap_as_string(literal: Option<scanner::LiteralValue>) -> String {
+fn unwrap_as_string(literal: Option<scanner::LiteralValue>) -> Option<String> {
     match literal {
-        Some(scanner::LiteralValue::StringValue(s)) => s.clone(),
-        Some(scanner::LiteralValue::IdentifierValue(s)) => s.clone(),
-        _ => panic!("Could not unwrap as string"),
+        Some(scanner::LiteralValue::StringValue(s)) => Some(s),
+        Some(scanner::LiteralValue::IdentifierValue(s)) => Some(s),
+        _ => None,
     }
 }
 
+impl fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &mut vec![])
+    }
+}
+
+/// The lists and maps a `write` call is already inside, so a container that
+/// holds itself is only walked once.
+pub(crate) type Seen = Vec<*const ()>;
+
+/// The pairs of lists and maps an `equals` call is already comparing.
+pub(crate) type Compared = Vec<(*const (), *const ())>;
+
+fn address<T: ?Sized>(container: &Rc<T>) -> *const () {
+    Rc::as_ptr(container) as *const ()
+}
+
 impl LiteralValue {
-    pub fn to_string(&self) -> String {
+    /// Formats the value for `Display`. A list or map met again inside
+    /// itself is shown as `[...]` or `{...}`.
+    pub(crate) fn write(&self, f: &mut fmt::Formatter, seen: &mut Seen) -> fmt::Result {
         match self {
-            LiteralValue::Number(x) => x.to_string(),
-            LiteralValue::StringValue(x) => format!("\"{}\"", x),
-            LiteralValue::True => "true".to_string(),
-            LiteralValue::False => "false".to_string(),
-            LiteralValue::Nil => "nill".to_string(),
+            LiteralValue::Int(x) => write!(f, "{}", x),
+            LiteralValue::BigInt(x) => write!(f, "{}", x),
+            LiteralValue::Rational(x) => write!(f, "{}", x),
+            // Debug formatting keeps the `.0` on whole floats so `3.0` and `3`
+            // print differently
+            LiteralValue::Float(x) => write!(f, "{:?}", x),
+            LiteralValue::StringValue(x) => write!(f, "\"{}\"", x),
+            LiteralValue::True => write!(f, "true"),
+            LiteralValue::False => write!(f, "false"),
+            LiteralValue::Nil => write!(f, "nill"),
+            LiteralValue::Callable(callable) => write!(f, "{}", callable),
+            LiteralValue::Instance(instance) => write!(f, "{}", instance),
+            LiteralValue::List(elements) => {
+                if seen.contains(&address(elements)) {
+                    return write!(f, "[...]");
+                }
+                seen.push(address(elements));
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    limits::deep(|| element.write(f, seen))?;
+                }
+                seen.pop();
+                write!(f, "]")
+            },
+            LiteralValue::Map(map) => {
+                if seen.contains(&address(map)) {
+                    return write!(f, "{{...}}");
+                }
+                seen.push(address(map));
+                map.borrow().write(f, seen)?;
+                seen.pop();
+                Ok(())
+            },
+            LiteralValue::Range(range) => write!(f, "{}", range),
+            LiteralValue::Iterator(_) => write!(f, "<iterator>"),
+            LiteralValue::UserData(data) => match data.try_borrow() {
+                Ok(data) => write!(f, "<{}>", data.type_name()),
+                Err(_) => write!(f, "<userdata>"),
+            },
+        }
+    }
+
+    /// Structural equality. A pair of containers met again while they are
+    /// still being compared counts as equal; any difference between them is
+    /// found by the comparison already in progress.
+    pub(crate) fn equals(&self, other: &Self, seen: &mut Compared) -> bool {
+        match (self, other) {
+            (x, y) if x.is_number() && y.is_number() => numeric::compare(x, y) == Some(std::cmp::Ordering::Equal),
+            (LiteralValue::StringValue(x), LiteralValue::StringValue(y)) => x == y,
+            (LiteralValue::True, LiteralValue::True) => true,
+            (LiteralValue::False, LiteralValue::False) => true,
+            (LiteralValue::Nil, LiteralValue::Nil) => true,
+            (LiteralValue::Callable(x), LiteralValue::Callable(y)) => x == y,
+            (LiteralValue::Instance(x), LiteralValue::Instance(y)) => x == y,
+            (LiteralValue::List(x), LiteralValue::List(y)) => compare_once((address(x), address(y)), seen, |seen| {
+                let (x, y) = (x.borrow(), y.borrow());
+                x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| x.equals(y, seen))
+            }),
+            (LiteralValue::Map(x), LiteralValue::Map(y)) => compare_once((address(x), address(y)), seen, |seen| {
+                x.borrow().equals(&y.borrow(), seen)
+            }),
+            (LiteralValue::Range(x), LiteralValue::Range(y)) => x == y,
+            (LiteralValue::Iterator(x), LiteralValue::Iterator(y)) => std::ptr::addr_eq(Rc::as_ptr(x), Rc::as_ptr(y)),
+            (LiteralValue::UserData(x), LiteralValue::UserData(y)) => std::ptr::addr_eq(Rc::as_ptr(x), Rc::as_ptr(y)),
+            _ => false,
         }
     }
+}
 
+/// Runs `compare` for two containers unless they are the same object or
+/// are already being compared further up.
+fn compare_once(
+    pair: (*const (), *const ()),
+    seen: &mut Compared,
+    compare: impl FnOnce(&mut Compared) -> bool,
+) -> bool {
+    if pair.0 == pair.1 || seen.contains(&pair) {
+        return true;
+    }
+    seen.push(pair);
+    let equal = limits::deep(|| compare(seen));
+    seen.pop();
+    equal
+}
+
+impl LiteralValue {
     pub fn to_type(&self) -> &str {
         match self {
-            LiteralValue::Number(_) => "Number",
+            LiteralValue::Int(_) => "Int",
+            LiteralValue::BigInt(_) => "BigInt",
+            LiteralValue::Rational(_) => "Rational",
+            LiteralValue::Float(_) => "Float",
             LiteralValue::StringValue(_) => "String",
             LiteralValue::True => "True",
             LiteralValue::False => "False",
             LiteralValue::Nil => "Nil",
+            LiteralValue::Callable(_) => "Callable",
+            LiteralValue::Instance(_) => "Instance",
+            LiteralValue::List(_) => "List",
+            LiteralValue::Map(_) => "Map",
+            LiteralValue::Range(_) => "Range",
+            LiteralValue::Iterator(_) => "Iterator",
+            LiteralValue::UserData(_) => "UserData",
         }
     }
 
-    pub fn from_token(token: Token) -> Self {
+    /// The value a literal token stands for, or `None` if `token` isn't a
+    /// literal or lacks the value the scanner attaches to it.
+    pub fn from_token(token: Token) -> Option<Self> {
         match token.token_type {
-            TokenType::Number => Self::Number(unwrap_as_f32(token.literal)),
-            TokenType::String => Self::StringValue(unwrap_as_string(token.literal)),
-            TokenType::False => Self::False,
-            TokenType::True => Self::True,
-            TokenType::Nil => Self::Nil,
-            _ => panic!("Could not create LiteralValue from {:?}", token)
+            TokenType::Number => unwrap_as_number(token.literal),
+            TokenType::String | TokenType::Interpolation => unwrap_as_string(token.literal).map(Self::StringValue),
+            TokenType::False => Some(Self::False),
+            TokenType::True => Some(Self::True),
+            TokenType::Nil => Some(Self::Nil),
+            _ => None,
+        }
+    }
+
+    /// The text an interpolated string shows for this value: the same as
+    /// `Display`, except that strings appear without quotes.
+    pub fn display(&self) -> String {
+        match self {
+            LiteralValue::StringValue(s) => s.clone(),
+            other => other.to_string(),
         }
     }
 
     pub fn is_falsy(&self) -> LiteralValue {
         match self {
-            LiteralValue::Number(x) => if *x == 0.0 {LiteralValue::True} else {LiteralValue::False},
-            LiteralValue::StringValue(s) => if s.len() == 0 {LiteralValue::True} else {LiteralValue::False},
+            LiteralValue::Int(_) | LiteralValue::BigInt(_) | LiteralValue::Rational(_) | LiteralValue::Float(_) => {
+                LiteralValue::from_bool(numeric::is_zero(self))
+            },
+            LiteralValue::StringValue(s) => if s.is_empty() {LiteralValue::True} else {LiteralValue::False},
             LiteralValue::True => LiteralValue::False,
             LiteralValue::False => LiteralValue::True,
             LiteralValue::Nil => LiteralValue::True,
+            LiteralValue::Callable(_) => LiteralValue::False,
+            LiteralValue::Instance(_) => LiteralValue::False,
+            LiteralValue::List(elements) => LiteralValue::from_bool(elements.borrow().is_empty()),
+            LiteralValue::Map(map) => LiteralValue::from_bool(map.borrow().is_empty()),
+            LiteralValue::Range(range) => LiteralValue::from_bool(range.is_empty()),
+            LiteralValue::Iterator(_) => LiteralValue::False,
+            LiteralValue::UserData(_) => LiteralValue::False,
         }
     }
 
     pub fn is_truthy(&self) -> LiteralValue {
         match self {
-            LiteralValue::Number(x) => if *x == 0.0 {LiteralValue::False} else {LiteralValue::True},
-            LiteralValue::StringValue(s) => if s.len() == 0 {LiteralValue::False} else {LiteralValue::True},
+            LiteralValue::Int(_) | LiteralValue::BigInt(_) | LiteralValue::Rational(_) | LiteralValue::Float(_) => {
+                LiteralValue::from_bool(!numeric::is_zero(self))
+            },
+            LiteralValue::StringValue(s) => if s.is_empty() {LiteralValue::False} else {LiteralValue::True},
             LiteralValue::True => LiteralValue::True,
             LiteralValue::False => LiteralValue::False,
             LiteralValue::Nil => LiteralValue::False,
+            LiteralValue::Callable(_) => LiteralValue::True,
+            LiteralValue::Instance(_) => LiteralValue::True,
+            LiteralValue::List(elements) => LiteralValue::from_bool(!elements.borrow().is_empty()),
+            LiteralValue::Map(map) => LiteralValue::from_bool(!map.borrow().is_empty()),
+            LiteralValue::Range(range) => LiteralValue::from_bool(!range.is_empty()),
+            LiteralValue::Iterator(_) => LiteralValue::True,
+            LiteralValue::UserData(_) => LiteralValue::True,
+        }
+    }
+
+    /// Applies a prefix operator. Shared by the tree-walker and the bytecode VM
+    /// so both backends agree on semantics and error messages.
+    pub fn unary_op(&self, operator: TokenType) -> Result<LiteralValue, String> {
+        match (self, operator) {
+            (_, TokenType::Minus) => numeric::negate(self)
+                .ok_or_else(|| format!("Minus not implemented for {}", self.to_type())),
+            (any, TokenType::Bang) => {
+                Ok(any.is_falsy())
+            }
+            (_, token_type) => Err(format!("{:?} is not a valid unary operator", token_type))
         }
     }
 
+    /// Applies an infix arithmetic, comparison or equality operator.
+    pub fn binary_op(&self, operator: TokenType, right: &LiteralValue) -> Result<LiteralValue, String> {
+        match (self, operator, right) {
+            (LiteralValue::Int(start), TokenType::DotDot | TokenType::DotDotEqual, LiteralValue::Int(end)) => {
+                Ok(LiteralValue::Range(LoxRange {
+                    start: *start,
+                    end: *end,
+                    inclusive: operator == TokenType::DotDotEqual,
+                }))
+            },
+            (x, TokenType::DotDot | TokenType::DotDotEqual, y) => {
+                Err(format!("Range bounds must be Int, got {} and {}", x.to_type(), y.to_type()))
+            },
+            (x, op, y) if x.is_number() && y.is_number() => numeric::binary(x, op, y),
+            (LiteralValue::StringValue(_), op, y) if y.is_number() => {
+                Err(format!("{:?} is not defined for string and numbers", op))
+            },
+            (x, op, LiteralValue::StringValue(_)) if x.is_number() => {
+                Err(format!("{:?} is not defined for string and numbers", op))
+            },
+            (LiteralValue::StringValue(s1), TokenType::Plus, LiteralValue::StringValue(s2)) => {
+                Ok(LiteralValue::StringValue(format!("{}{}", s1, s2)))
+            },
+            (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
+            (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
+            
+            (LiteralValue::StringValue(s1), TokenType::Greater, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 > s2)),
+            (LiteralValue::StringValue(s1), TokenType::GreaterEqual, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 >= s2)),
+            (LiteralValue::StringValue(s1), TokenType::Less, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 < s2)),
+            (LiteralValue::StringValue(s1), TokenType::LessEqual, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 <= s2)),
+            (x, token_type, y) => {
+                Err(format!("{:?} is not implemented for operands {} and {}", token_type, x.to_type(), y.to_type()))
+            }
+        }
+    }
+
+    pub fn list(elements: Vec<LiteralValue>) -> Self {
+        LiteralValue::List(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn userdata(data: impl UserData) -> Self {
+        LiteralValue::UserData(Rc::new(RefCell::new(data)))
+    }
+
+    /// Borrows the host object inside this value if it is a `T`. Gives
+    /// `None` for other values, or while a method of the object is running.
+    pub fn downcast_ref<T: UserData>(&self) -> Option<Ref<'_, T>> {
+        match self {
+            LiteralValue::UserData(data) => {
+                Ref::filter_map(data.try_borrow().ok()?, |data| (data as &dyn Any).downcast_ref()).ok()
+            },
+            _ => None,
+        }
+    }
+
+    pub fn downcast_mut<T: UserData>(&self) -> Option<RefMut<'_, T>> {
+        match self {
+            LiteralValue::UserData(data) => {
+                RefMut::filter_map(data.try_borrow_mut().ok()?, |data| (data as &mut dyn Any).downcast_mut()).ok()
+            },
+            _ => None,
+        }
+    }
+
+    /// Reads `self[index]`. Negative list indices count back from the end;
+    /// a missing map key is an error.
+    pub fn get_index(&self, index: &LiteralValue) -> Result<LiteralValue, String> {
+        match self {
+            LiteralValue::List(elements) => {
+                let elements = elements.borrow();
+                let position = list_position(index, elements.len())?;
+                Ok(elements[position].clone())
+            },
+            LiteralValue::Map(map) => map.borrow().get(index),
+            other => Err(format!("{} is not indexable", other.to_type())),
+        }
+    }
+
+    /// Performs `self[index] = value`.
+    pub fn set_index(&self, index: &LiteralValue, value: LiteralValue) -> Result<(), String> {
+        match self {
+            LiteralValue::List(elements) => {
+                let mut elements = elements.borrow_mut();
+                let position = list_position(index, elements.len())?;
+                elements[position] = value;
+                Ok(())
+            },
+            LiteralValue::Map(map) => map.borrow_mut().insert(index.clone(), value),
+            other => Err(format!("{} does not support index assignment", other.to_type())),
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(
+            self,
+            LiteralValue::Int(_) | LiteralValue::BigInt(_) | LiteralValue::Rational(_) | LiteralValue::Float(_)
+        )
+    }
+
     pub fn from_bool(b: bool) -> Self {
         if b {
             LiteralValue::True
@@ -91,9 +374,28 @@ impl LiteralValue {
     }
 }
 
+impl PartialEq for LiteralValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut vec![])
+    }
+}
+
+fn list_position(index: &LiteralValue, len: usize) -> Result<usize, String> {
+    let index = match index {
+        LiteralValue::Int(index) => *index,
+        other => return Err(format!("List index must be an Int, got {}", other.to_type())),
+    };
+    let position = if index < 0 { index + len as i64 } else { index };
+    if position < 0 || position >= len as i64 {
+        return Err(format!("Index {} out of bounds for list of length {}", index, len));
+    }
+    Ok(position as usize)
+}
+
 #[derive(Debug)]
 pub enum Expr {
     Assign {
+        id: usize,
         name: Token,
         value: Box<Expr>
     },
@@ -102,143 +404,356 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>
     },
-    Grouping { expression: Box<Expr> },
-    Literal { value: LiteralValue},
+    /// `span` covers the parentheses.
+    Grouping { span: Span, expression: Box<Expr> },
+    Literal { span: Span, value: LiteralValue },
     Unary { operator: Token, right: Box<Expr> },
-    Variable { name: Token },
+    Variable { id: usize, name: Token },
     Logical { left: Box<Expr>, operator: Token, right: Box<Expr> },
     Call {
         calee: Box<Expr>,
         paren: Token,
         arguments: Vec<Expr>,
     },
+    Get { object: Box<Expr>, name: Token },
+    Set { object: Box<Expr>, name: Token, value: Box<Expr> },
+    This { id: usize, keyword: Token },
+    Super { id: usize, keyword: Token, method: Token },
+    /// `bracket` is the closing `]`; `span` covers the whole literal.
+    List { span: Span, bracket: Token, elements: Vec<Expr> },
+    /// `brace` is the closing `}`; `span` covers the whole literal.
+    Map { span: Span, brace: Token, entries: Vec<(Expr, Expr)> },
+    Index { object: Box<Expr>, bracket: Token, index: Box<Expr> },
+    IndexSet { object: Box<Expr>, bracket: Token, index: Box<Expr>, value: Box<Expr> },
+    /// `"a ${b} c"`: string segments and embedded expressions, in order.
+    Interpolation { span: Span, quote: Token, parts: Vec<Expr> },
 }
 
 impl Expr {
-    #[allow(dead_code)]
-    pub fn to_string(&self) -> String {
+    /// The source text this expression was parsed from.
+    pub fn span(&self) -> Span {
+        limits::deep(|| match self {
+            Expr::Assign { id: _, name, value } => name.span.to(value.span()),
+            Expr::Binary { left, operator: _, right } => left.span().to(right.span()),
+            Expr::Grouping { span, expression: _ } => *span,
+            Expr::Literal { span, value: _ } => *span,
+            Expr::Unary { operator, right } => operator.span.to(right.span()),
+            Expr::Variable { id: _, name } => name.span,
+            Expr::Logical { left, operator: _, right } => left.span().to(right.span()),
+            Expr::Call { calee, paren, arguments: _ } => calee.span().to(paren.span),
+            Expr::Get { object, name } => object.span().to(name.span),
+            Expr::Set { object, name: _, value } => object.span().to(value.span()),
+            Expr::This { id: _, keyword } => keyword.span,
+            Expr::Super { id: _, keyword, method } => keyword.span.to(method.span),
+            Expr::List { span, bracket: _, elements: _ } => *span,
+            Expr::Map { span, brace: _, entries: _ } => *span,
+            Expr::Index { object, bracket, index: _ } => object.span().to(bracket.span),
+            Expr::IndexSet { object, bracket: _, index: _, value } => object.span().to(value.span()),
+            Expr::Interpolation { span, quote: _, parts: _ } => *span,
+        })
+    }
+
+    /// Moves `expr` out, leaving a `nil` literal in its place. Fields can't
+    /// be moved out of an `Expr` directly because it implements `Drop`.
+    pub(crate) fn take(expr: &mut Expr) -> Expr {
+        std::mem::replace(expr, Expr::Literal { span: Span::default(), value: LiteralValue::Nil })
+    }
+
+    /// Moves the subexpressions of `self` into `into`.
+    fn take_children(&mut self, into: &mut Vec<Expr>) {
         match self {
+            Expr::Assign { value: child, .. }
+            | Expr::Grouping { expression: child, .. }
+            | Expr::Unary { right: child, .. }
+            | Expr::Get { object: child, .. } => into.push(Expr::take(child)),
+            Expr::Binary { left, right, .. }
+            | Expr::Logical { left, right, .. }
+            | Expr::Set { object: left, value: right, .. }
+            | Expr::Index { object: left, index: right, .. } => {
+                into.push(Expr::take(left));
+                into.push(Expr::take(right));
+            },
+            Expr::IndexSet { object, index, value, .. } => {
+                into.extend([Expr::take(object), Expr::take(index), Expr::take(value)]);
+            },
+            Expr::Call { calee, arguments, .. } => {
+                into.push(Expr::take(calee));
+                into.append(arguments);
+            },
+            Expr::List { elements: children, .. } | Expr::Interpolation { parts: children, .. } => {
+                into.append(children);
+            },
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries.drain(..) {
+                    into.extend([key, value]);
+                }
+            },
+            Expr::Literal { .. } | Expr::Variable { .. } | Expr::This { .. } | Expr::Super { .. } => (),
+        }
+    }
+}
+
+/// Frees the tree with a loop instead of recursion, so dropping a long
+/// chain like `1 + 1 + ... + 1` can't overflow the stack.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut pending = vec![];
+        self.take_children(&mut pending);
+        while let Some(mut expr) = pending.pop() {
+            expr.take_children(&mut pending);
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        limits::deep(|| match self {
             Expr::Assign {
+                id: _,
                 name,
                 value 
-            } => format!("({:?}={})", name, value.to_string()),
+            } => write!(f, "(= {} {})", name.lexeme, value),
             Expr::Binary {
                 left,
                 operator,
                 right
-            } => format!(
+            } => write!(
+                f,
                 "({} {} {})",
                 operator.lexeme,
-                left.to_string(),
-                right.to_string()
+                left,
+                right
             ),
-            Expr::Grouping { expression } => format!("(group {})", (*expression).to_string()),
-            Expr::Literal { value } => format!("{}", value.to_string()),
+            Expr::Grouping { span: _, expression } => write!(f, "(group {})", expression),
+            Expr::Literal { span: _, value } => write!(f, "{}", value),
             Expr::Unary { operator, right } => {
-                let operator_str = operator.lexeme.clone();
-                let right_str = (*right).to_string();
-                format!("({} {})", operator_str, right_str)
+                write!(f, "({} {})", operator.lexeme, right)
             },
-            Expr::Variable { name } => format!("(var {})", name.lexeme),
-            Expr::Logical { left, operator, right } => format!(
-                "({} {} {})", operator.to_string(), left.to_string(), right.to_string()
+            Expr::Variable { id: _, name } => write!(f, "(var {})", name.lexeme),
+            Expr::Logical { left, operator, right } => write!(
+                f, "({} {} {})", operator.lexeme, left, right
             ),
-            Expr::Call { calee, paren, arguments } => format!("(call {} {} {:?})", calee.to_string(), paren.to_string(), arguments),
-        }
+            Expr::Call { calee, paren: _, arguments } => write!(
+                f,
+                "(call {}{})",
+                calee,
+                arguments.iter().map(|argument| format!(" {}", argument)).collect::<String>()
+            ),
+            Expr::Get { object, name } => write!(f, "(get {} {})", object, name.lexeme),
+            Expr::Set { object, name, value } => write!(f, "(set {} {} {})", object, name.lexeme, value),
+            Expr::This { id: _, keyword: _ } => write!(f, "this"),
+            Expr::Super { id: _, keyword: _, method } => write!(f, "(super {})", method.lexeme),
+            Expr::List { span: _, bracket: _, elements } => write!(
+                f,
+                "(list{})",
+                elements.iter().map(|element| format!(" {}", element)).collect::<String>()
+            ),
+            Expr::Map { span: _, brace: _, entries } => write!(
+                f,
+                "(map{})",
+                entries.iter().map(|(key, value)| format!(" ({} {})", key, value)).collect::<String>()
+            ),
+            Expr::Index { object, bracket: _, index } => write!(f, "(index {} {})", object, index),
+            Expr::IndexSet { object, bracket: _, index, value } => {
+                write!(f, "(index-set {} {} {})", object, index, value)
+            },
+            Expr::Interpolation { span: _, quote: _, parts } => write!(
+                f,
+                "(interpolate{})",
+                parts.iter().map(|part| format!(" {}", part)).collect::<String>()
+            ),
+        })
+    }
+}
+
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl Expr {
+    /// Hands out a fresh id for expressions the resolver annotates. Ids are
+    /// unique for the whole process so REPL lines never collide.
+    pub fn next_id() -> usize {
+        NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
     }
 
-    pub fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Result<LiteralValue, String> {
+    pub fn evaluate(&self, interpreter: &mut Interpreter) -> Result<LiteralValue, RuntimeError> {
+        limits::deep(|| self.evaluate_node(interpreter))
+    }
+
+    fn evaluate_node(&self, interpreter: &mut Interpreter) -> Result<LiteralValue, RuntimeError> {
         match self {
-            Expr::Assign { name, value } => {
-                let new_value = (*value).evaluate(environment.clone())?;
-                let assign_success = environment.borrow_mut().assign(&name.lexeme, new_value.clone());
+            Expr::Assign { id, name, value } => {
+                let new_value = (*value).evaluate(interpreter)?;
+                let assign_success = interpreter.assign_variable(*id, &name.lexeme, new_value.clone());
                 if assign_success {
                     Ok(new_value)
                 } else {
-                    Err(format!("Variable {} has not been declared.", name.lexeme))
+                    Err(RuntimeError::UndefinedVariable { span: name.span, name: name.lexeme.clone() })
                 }
             }
-            Expr::Variable { name } => match environment.borrow().get(&name.lexeme) {
-                Some(value) => Ok(value.clone()),
-                None => Err(format!("Variable '{}' has not been declared", name.lexeme))
+            Expr::Variable { id, name } => match interpreter.look_up_variable(*id, &name.lexeme) {
+                Some(value) => Ok(value),
+                None => Err(RuntimeError::UndefinedVariable { span: name.span, name: name.lexeme.clone() }),
             },
-            Expr::Literal { value } => Ok((*value).clone()),
-            Expr::Grouping { expression } => expression.evaluate(environment),
+            Expr::Literal { span: _, value } => Ok((*value).clone()),
+            Expr::Grouping { span: _, expression } => expression.evaluate(interpreter),
             Expr::Unary { operator, right } => {
-                let right = right.evaluate(environment)?;
-                match (&right, operator.token_type) {
-                    (LiteralValue::Number(x), TokenType::Minus) => Ok(LiteralValue::Number(-x)),
-                    (_, TokenType::Minus) => return Err(format!("Minus not implemented for {}", right.to_type())),
-                    (any, TokenType::Bang) => {
-                        Ok(any.is_falsy())
-                    }
-                    (_, token_type) => Err(format!("{:?} is not a valid unary operator", token_type))
-                }
+                let right = right.evaluate(interpreter)?;
+                right.unary_op(operator.token_type).map_err(|msg| RuntimeError::invalid(operator.span, msg))
             },
             Expr::Binary { left, operator, right } => {
-                let left = left.evaluate(environment.clone())?;
-                let right = right.evaluate(environment.clone())?;
-
-                match (&left, operator.token_type, &right) {
-                    (LiteralValue::Number(x), TokenType::Plus, LiteralValue::Number(y)) => Ok(LiteralValue::Number(x + y)),
-                    (LiteralValue::Number(x), TokenType::Minus, LiteralValue::Number(y)) => Ok(LiteralValue::Number(x - y)),
-                    (LiteralValue::Number(x), TokenType::Star, LiteralValue::Number(y)) => Ok(LiteralValue::Number(x * y)),
-                    (LiteralValue::Number(x), TokenType::Slash, LiteralValue::Number(y)) => Ok(LiteralValue::Number(x / y)),
-                    (LiteralValue::Number(x), TokenType::Percent, LiteralValue::Number(y)) => Ok(LiteralValue::Number(x % y)),
-                    (LiteralValue::Number(x), TokenType::Less, LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x < y)),
-                    (LiteralValue::Number(x), TokenType::LessEqual, LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x <= y)),
-                    (LiteralValue::Number(x), TokenType::Greater, LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x > y)),
-                    (LiteralValue::Number(x), TokenType::GreaterEqual, LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x >= y)),
-                    (LiteralValue::StringValue(_), op, LiteralValue::Number(_)) => {
-                        Err(format!("{:?} is not defined for string and numbers", op))
-                    },
-                    (LiteralValue::Number(_), op, LiteralValue::StringValue(_)) => {
-                        Err(format!("{:?} is not defined for string and numbers", op))
-                    },
-                    (LiteralValue::StringValue(s1), TokenType::Plus, LiteralValue::StringValue(s2)) => {
-                        Ok(LiteralValue::StringValue(format!("{}{}", s1, s2)))
-                    },
-                    (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
-                    (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
-                    
-                    (LiteralValue::StringValue(s1), TokenType::Greater, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 > s2)),
-                    (LiteralValue::StringValue(s1), TokenType::GreaterEqual, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 >= s2)),
-                    (LiteralValue::StringValue(s1), TokenType::Less, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 < s2)),
-                    (LiteralValue::StringValue(s1), TokenType::LessEqual, LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 <= s2)),
-                    (x, token_type, y) => {
-                        Err(format!("{:?} is not implemented for operands {:?} and {:?}", token_type, x, y))
-                    }
-                }
+                let left = left.evaluate(interpreter)?;
+                let right = right.evaluate(interpreter)?;
+                left.binary_op(operator.token_type, &right).map_err(|msg| RuntimeError::invalid(operator.span, msg))
             },
             Expr::Logical { left, operator, right } => {
                 match operator.token_type {
                     TokenType::Or => {
-                        let lhs_value = left.evaluate(environment.clone())?;
+                        let lhs_value = left.evaluate(interpreter)?;
                         let lhs_true = lhs_value.is_truthy();
                         if lhs_true == LiteralValue::True {
                             Ok(lhs_value)
                         } else {
-                            right.evaluate(environment.clone())
+                            right.evaluate(interpreter)
                         }
                     },
                     TokenType::And => {
-                        let lhs_value = left.evaluate(environment.clone())?;
+                        let lhs_value = left.evaluate(interpreter)?;
                         let lsh_true = lhs_value.is_truthy();
                         if lsh_true == LiteralValue::False {
                             Ok(lsh_true)
                         } else {
-                            right.evaluate(environment.clone())
+                            right.evaluate(interpreter)
                         }
                     },
-                    token_type => Err(format!("Invalid token in logical expression: {:?}", token_type)),
+                    token_type => Err(RuntimeError::invalid(
+                        operator.span,
+                        format!("Invalid token in logical expression: {:?}", token_type),
+                    )),
+                }
+            },
+            Expr::Call { calee, paren, arguments } => {
+                let callable = match calee.evaluate(interpreter)? {
+                    LiteralValue::Callable(callable) => callable,
+                    other => return Err(RuntimeError::NotCallable {
+                        span: paren.span,
+                        type_name: other.to_type().to_string(),
+                    }),
+                };
+
+                let mut arg_values = vec![];
+                for argument in arguments {
+                    arg_values.push(argument.evaluate(interpreter)?);
+                }
+
+                if let Some(arity) = callable.arity().filter(|arity| *arity != arg_values.len()) {
+                    return Err(RuntimeError::WrongArity {
+                        span: paren.span,
+                        callee: callable.name().to_string(),
+                        expected: arity,
+                        got: arg_values.len(),
+                    });
+                }
+                // errors from a script body already say where they happened
+                interpreter.call_nested(|interpreter| callable.call(interpreter, arg_values))
+                    .map_err(|error| error.at(paren.span))
+            },
+            Expr::Get { object, name } => match object.evaluate(interpreter)? {
+                LiteralValue::Instance(instance) => {
+                    instance.get(&name.lexeme).map_err(|msg| RuntimeError::invalid(name.span, msg))
+                },
+                LiteralValue::UserData(data) => {
+                    userdata::get_property(&data, &name.lexeme).map_err(|error| error.at(name.span))
+                },
+                other => Err(RuntimeError::invalid(name.span, format!(
+                    "Only instances have properties, got {} for '{}'", other.to_type(), name.lexeme
+                ))),
+            },
+            Expr::Set { object, name, value } => match object.evaluate(interpreter)? {
+                LiteralValue::Instance(instance) => {
+                    let value = value.evaluate(interpreter)?;
+                    instance.set(&name.lexeme, value.clone());
+                    Ok(value)
+                },
+                LiteralValue::UserData(data) => {
+                    let value = value.evaluate(interpreter)?;
+                    userdata::set_property(&data, &name.lexeme, value.clone()).map_err(|error| error.at(name.span))?;
+                    Ok(value)
+                },
+                other => Err(RuntimeError::invalid(name.span, format!(
+                    "Only instances have fields, got {} for '{}'", other.to_type(), name.lexeme
+                ))),
+            },
+            Expr::This { id, keyword } => match interpreter.look_up_variable(*id, &keyword.lexeme) {
+                Some(value) => Ok(value),
+                None => Err(RuntimeError::invalid(keyword.span, "Can't use 'this' outside of a class".to_string())),
+            },
+            Expr::Super { id, keyword, method } => {
+                let superclass = match interpreter.look_up_variable(*id, &keyword.lexeme) {
+                    Some(LiteralValue::Callable(Callable::Class(class))) => class,
+                    _ => return Err(RuntimeError::invalid(
+                        keyword.span,
+                        "Can't use 'super' in a class with no superclass".to_string(),
+                    )),
+                };
+                // `this` is bound in the scope just inside the one holding `super`.
+                let this = match interpreter.depth_of(*id) {
+                    Some(depth) if depth > 0 => interpreter.environment.borrow().get_at(depth - 1, "this"),
+                    _ => None,
+                };
+                let instance = match this {
+                    Some(LiteralValue::Instance(instance)) => instance,
+                    _ => {
+                        let msg = "Can't use 'super' outside of a method".to_string();
+                        return Err(RuntimeError::invalid(keyword.span, msg));
+                    },
+                };
+                match superclass.find_method(&method.lexeme) {
+                    Some(found) => Ok(LiteralValue::Callable(found.bind(instance))),
+                    None => Err(RuntimeError::invalid(keyword.span, format!(
+                        "Undefined property '{}' on superclass {}", method.lexeme, superclass.name
+                    ))),
+                }
+            },
+            Expr::List { span: _, bracket: _, elements } => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements {
+                    values.push(element.evaluate(interpreter)?);
+                }
+                Ok(LiteralValue::list(values))
+            },
+            Expr::Map { span: _, brace, entries } => {
+                let mut map = LoxMap::default();
+                for (key, value) in entries {
+                    let key = key.evaluate(interpreter)?;
+                    let value = value.evaluate(interpreter)?;
+                    map.insert(key, value).map_err(|msg| RuntimeError::invalid(brace.span, msg))?;
+                }
+                Ok(LiteralValue::Map(Rc::new(RefCell::new(map))))
+            },
+            Expr::Index { object, bracket, index } => {
+                let object = object.evaluate(interpreter)?;
+                let index = index.evaluate(interpreter)?;
+                object.get_index(&index).map_err(|msg| RuntimeError::invalid(bracket.span, msg))
+            },
+            Expr::IndexSet { object, bracket, index, value } => {
+                let object = object.evaluate(interpreter)?;
+                let index = index.evaluate(interpreter)?;
+                let value = value.evaluate(interpreter)?;
+                object.set_index(&index, value.clone()).map_err(|msg| RuntimeError::invalid(bracket.span, msg))?;
+                Ok(value)
+            },
+            Expr::Interpolation { span: _, quote: _, parts } => {
+                let mut text = String::new();
+                for part in parts {
+                    text.push_str(&part.evaluate(interpreter)?.display());
                 }
+                Ok(LiteralValue::StringValue(text))
             },
-            Expr::Call { calee: _, paren: _, arguments: _ } => todo!(),
         }
     }
 
     #[allow(dead_code)]
     pub fn print(&self) {
-        println!("{}", self.to_string());
+        println!("{}", self);
     }
 }
\ No newline at end of file
@@ -0,0 +1,165 @@
+use crate::ast::LiteralValue;
+use crate::class::{LoxClass, LoxInstance};
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::interpreter::{Interpreter, Unwind};
+use crate::natives::NativeFunction;
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+use crate::vm::{BoundMethod, Closure};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// A function declared in a script with `fun`, together with the
+/// environment it was declared in.
+pub struct LoxFunction {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Rc<Vec<Stmt>>,
+    pub closure: Rc<RefCell<Environment>>,
+    pub is_initializer: bool,
+}
+
+impl LoxFunction {
+    /// Returns a copy of this method whose closure has `this` bound to `instance`.
+    pub fn bind(&self, instance: LoxInstance) -> LoxFunction {
+        let mut environment = Environment::new();
+        environment.enclosing = Some(self.closure.clone());
+        environment.define("this".to_string(), LiteralValue::Instance(instance));
+        LoxFunction {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: self.body.clone(),
+            closure: Rc::new(RefCell::new(environment)),
+            is_initializer: self.is_initializer,
+        }
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> Result<LiteralValue, RuntimeError> {
+        let mut environment = Environment::new();
+        environment.enclosing = Some(self.closure.clone());
+        for (param, argument) in self.params.iter().zip(arguments) {
+            environment.define(param.lexeme.clone(), argument);
+        }
+
+        let value = match interpreter.execute_block(&self.body, environment) {
+            Ok(()) => LiteralValue::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(Unwind::Error(error)) => return Err(error),
+            Err(Unwind::Break(_) | Unwind::Continue(_)) => {
+                return Err("Can't use 'break' or 'continue' outside of a loop".to_string().into());
+            },
+        };
+
+        if self.is_initializer {
+            // `init` always hands back the instance, even on a bare `return;`
+            return self.closure.borrow().get("this")
+                .ok_or_else(|| "Initializer is not bound to an instance".to_string().into());
+        }
+        Ok(value)
+    }
+}
+
+/// Anything that can appear before `(` in a call. `Function` runs on the
+/// tree-walker; `Closure` and `BoundMethod` are produced by the bytecode VM.
+/// `Native` works on both.
+#[derive(Clone)]
+pub enum Callable {
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+    Native(Rc<NativeFunction>),
+}
+
+impl Callable {
+    pub fn name(&self) -> &str {
+        match self {
+            Callable::Function(function) => &function.name.lexeme,
+            Callable::Class(class) => &class.name,
+            Callable::Closure(closure) => &closure.function.name,
+            Callable::BoundMethod(bound) => &bound.method.function.name,
+            Callable::Native(native) => &native.name,
+        }
+    }
+
+    /// How many arguments a call must pass, or `None` for natives that
+    /// accept any number.
+    pub fn arity(&self) -> Option<usize> {
+        match self {
+            Callable::Function(function) => Some(function.params.len()),
+            Callable::Class(class) => match class.find_method("init") {
+                Some(initializer) => initializer.arity(),
+                None => Some(0),
+            },
+            Callable::Closure(closure) => Some(closure.function.arity),
+            Callable::BoundMethod(bound) => Some(bound.method.function.arity),
+            Callable::Native(native) => native.arity,
+        }
+    }
+
+    /// Binds a method to `instance` so that `this` refers to it.
+    pub fn bind(&self, instance: LoxInstance) -> Callable {
+        match self {
+            Callable::Function(function) => Callable::Function(Rc::new(function.bind(instance))),
+            Callable::Closure(closure) => Callable::BoundMethod(Rc::new(BoundMethod {
+                receiver: instance,
+                method: closure.clone(),
+            })),
+            other => other.clone(),
+        }
+    }
+
+    /// Runs the callable on the tree-walker. Errors that don't come from a
+    /// script body have no span yet; the caller locates them.
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, RuntimeError> {
+        match self {
+            Callable::Function(function) => function.call(interpreter, arguments),
+            Callable::Class(class) => {
+                let instance = LoxInstance::new(class.clone());
+                if let Some(initializer) = class.find_method("init") {
+                    initializer.bind(instance.clone()).call(interpreter, arguments)?;
+                }
+                Ok(LiteralValue::Instance(instance))
+            },
+            Callable::Native(native) => Ok((native.function)(&arguments)?),
+            Callable::Closure(_) | Callable::BoundMethod(_) => Err(format!(
+                "Function '{}' was compiled to bytecode and can only run on the VM", self.name()
+            ).into()),
+        }
+    }
+}
+
+impl fmt::Display for Callable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Callable::Class(class) => write!(f, "{}", class.name),
+            Callable::Native(native) => write!(f, "<native fn {}>", native.name),
+            function => write!(f, "<fn {}>", function.name()),
+        }
+    }
+}
+
+impl fmt::Debug for Callable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl PartialEq for Callable {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Callable::Function(a), Callable::Function(b)) => Rc::ptr_eq(a, b),
+            (Callable::Class(a), Callable::Class(b)) => Rc::ptr_eq(a, b),
+            (Callable::Closure(a), Callable::Closure(b)) => Rc::ptr_eq(a, b),
+            (Callable::BoundMethod(a), Callable::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Callable::Native(a), Callable::Native(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::ast::LiteralValue;
+use crate::span::Span;
+use std::rc::Rc;
+
+/// Instructions understood by the bytecode VM. Operands follow the opcode
+/// byte in the code stream; the comment on each variant lists them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// u16 constant index
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// u8 stack slot
+    GetLocal,
+    /// u8 stack slot
+    SetLocal,
+    /// u16 name constant
+    GetGlobal,
+    /// u16 name constant
+    DefineGlobal,
+    /// u16 name constant
+    SetGlobal,
+    /// u8 upvalue index
+    GetUpvalue,
+    /// u8 upvalue index
+    SetUpvalue,
+    /// u16 name constant
+    GetProperty,
+    /// u16 name constant
+    SetProperty,
+    /// u16 name constant
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Not,
+    Negate,
+    Print,
+    /// u16 forward offset
+    Jump,
+    /// u16 forward offset, leaves the condition on the stack
+    JumpIfFalse,
+    /// u16 backward offset
+    Loop,
+    /// u8 argument count
+    Call,
+    /// u16 function index, then (is_local: u8, index: u8) per upvalue
+    Closure,
+    CloseUpvalue,
+    Return,
+    /// u16 name constant, u8 method count, u8 has-superclass flag
+    Class,
+    /// u16 element count
+    BuildList,
+    GetIndex,
+    SetIndex,
+    /// u16 entry count
+    BuildMap,
+    Range,
+    RangeInclusive,
+    /// u16 part count; joins the parts' display text into one string
+    BuildString,
+    /// Replaces the value on top of the stack with an iterator over it.
+    GetIter,
+    /// u16 forward offset taken when the iterator on top of the stack is
+    /// exhausted; otherwise pushes its next value
+    ForIter,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 46] = [
+        OpCode::Constant, OpCode::Nil, OpCode::True, OpCode::False, OpCode::Pop,
+        OpCode::GetLocal, OpCode::SetLocal, OpCode::GetGlobal, OpCode::DefineGlobal,
+        OpCode::SetGlobal, OpCode::GetUpvalue, OpCode::SetUpvalue, OpCode::GetProperty,
+        OpCode::SetProperty, OpCode::GetSuper, OpCode::Equal, OpCode::NotEqual,
+        OpCode::Greater, OpCode::GreaterEqual, OpCode::Less, OpCode::LessEqual,
+        OpCode::Add, OpCode::Subtract, OpCode::Multiply, OpCode::Divide, OpCode::Modulo,
+        OpCode::Not, OpCode::Negate, OpCode::Print, OpCode::Jump, OpCode::JumpIfFalse,
+        OpCode::Loop, OpCode::Call, OpCode::Closure, OpCode::CloseUpvalue,
+        OpCode::Return, OpCode::Class, OpCode::BuildList, OpCode::GetIndex,
+        OpCode::SetIndex, OpCode::BuildMap, OpCode::Range, OpCode::RangeInclusive,
+        OpCode::BuildString, OpCode::GetIter, OpCode::ForIter,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+}
+
+/// A compiled function body before it has captured any variables.
+#[derive(Debug)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+/// A sequence of bytecode with the constants and nested functions it refers to.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    /// Source span of every byte in `code`, for runtime errors.
+    pub spans: Vec<Span>,
+    pub constants: Vec<LiteralValue>,
+    pub functions: Vec<Rc<FunctionProto>>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        self.spans.push(span);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, span: Span) {
+        self.write(op as u8, span);
+    }
+
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        let [high, low] = value.to_be_bytes();
+        self.write(high, span);
+        self.write(low, span);
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Adds `value` to the constant pool, reusing an existing entry for
+    /// equal strings and numbers.
+    pub fn add_constant(&mut self, value: LiteralValue) -> usize {
+        let existing = self.constants.iter().position(|constant| match (constant, &value) {
+            (LiteralValue::Int(a), LiteralValue::Int(b)) => a == b,
+            (LiteralValue::BigInt(a), LiteralValue::BigInt(b)) => a == b,
+            (LiteralValue::Float(a), LiteralValue::Float(b)) => a.to_bits() == b.to_bits(),
+            (LiteralValue::StringValue(a), LiteralValue::StringValue(b)) => a == b,
+            _ => false,
+        });
+        if let Some(index) = existing {
+            return index;
+        }
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn add_function(&mut self, function: FunctionProto) -> usize {
+        self.functions.push(Rc::new(function));
+        self.functions.len() - 1
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes_round_trip_through_bytes() {
+        for op in OpCode::ALL {
+            assert_eq!(OpCode::from_byte(op as u8), Some(op));
+        }
+        assert_eq!(OpCode::from_byte(OpCode::ALL.len() as u8), None);
+    }
+
+    #[test]
+    fn constants_are_deduplicated() {
+        let mut chunk = Chunk::default();
+        let a = chunk.add_constant(LiteralValue::StringValue("x".to_string()));
+        let b = chunk.add_constant(LiteralValue::Int(1));
+        let c = chunk.add_constant(LiteralValue::StringValue("x".to_string()));
+        assert_eq!(a, c);
+        assert_ne!(a, b);
+        assert_eq!(chunk.constants.len(), 2);
+    }
+}
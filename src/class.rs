@@ -0,0 +1,74 @@
+use crate::ast::LiteralValue;
+use crate::callable::Callable;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    /// Script functions for the tree-walker, compiled closures for the VM.
+    pub methods: HashMap<String, Callable>,
+}
+
+impl LoxClass {
+    /// Looks up a method on this class, then up the superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Callable> {
+        match (self.methods.get(name), &self.superclass) {
+            (Some(method), _) => Some(method.clone()),
+            (None, Some(superclass)) => superclass.find_method(name),
+            (None, None) => None,
+        }
+    }
+}
+
+/// An instance of a script-defined class. Cloning shares the field table,
+/// so every copy refers to the same object.
+#[derive(Clone)]
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    fields: Rc<RefCell<HashMap<String, LiteralValue>>>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Looks up a field first and falls back to a method bound to this instance.
+    pub fn get(&self, name: &str) -> Result<LiteralValue, String> {
+        if let Some(value) = self.fields.borrow().get(name) {
+            return Ok(value.clone());
+        }
+        match self.class.find_method(name) {
+            Some(method) => Ok(LiteralValue::Callable(method.bind(self.clone()))),
+            None => Err(format!("Undefined property '{}' on {} instance", name, self.class.name)),
+        }
+    }
+
+    pub fn set(&self, name: &str, value: LiteralValue) {
+        self.fields.borrow_mut().insert(name.to_string(), value);
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl PartialEq for LoxInstance {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.fields, &other.fields)
+    }
+}
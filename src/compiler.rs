@@ -0,0 +1,834 @@
+use crate::ast::{Expr, LiteralValue};
+use crate::chunk::{Chunk, FunctionProto, OpCode};
+use crate::error::CompileError;
+use crate::limits;
+use crate::scanner::{Token, TokenType};
+use crate::span::Span;
+use crate::stmt::Stmt;
+use std::rc::Rc;
+
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    /// `None` while the variable's initializer is being compiled.
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+/// A loop being compiled, so `break` and `continue` know where to jump and
+/// which locals to discard on the way.
+struct LoopState {
+    label: Option<String>,
+    /// Number of locals that live outside the loop body.
+    local_count: usize,
+    /// Where `continue` loops back to, or `None` when the target (the `for`
+    /// increment) comes after the body and has to be patched in later.
+    continue_target: Option<usize>,
+    continue_jumps: Vec<usize>,
+    break_jumps: Vec<usize>,
+}
+
+/// Compilation state for one function body; nested declarations push a new one.
+struct FunctionState {
+    name: String,
+    arity: usize,
+    kind: FunctionKind,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: FunctionKind) -> Self {
+        // Slot zero holds the callee, or the receiver for methods.
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        Self {
+            name: name.to_string(),
+            arity: 0,
+            kind,
+            chunk: Chunk::default(),
+            locals: vec![Local { name: slot_zero.to_string(), depth: Some(0), is_captured: false }],
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+/// Translates the parsed `Stmt`/`Expr` tree into bytecode for the `Vm`.
+/// Performs the same static checks as the `Resolver`, since the bytecode
+/// backend does not run it.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+    /// Label of a `label: ...` statement, taken by the loop that follows it.
+    pending_label: Option<String>,
+    /// Span of the source being compiled, recorded for every emitted byte.
+    span: Span,
+    errors: Vec<CompileError>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            functions: vec![FunctionState::new("script", FunctionKind::Script)],
+            classes: vec![],
+            pending_label: None,
+            span: Span::default(),
+            errors: vec![],
+        }
+    }
+
+    /// Compiles a whole program into the function the VM runs as its entry point.
+    pub fn compile(mut self, stmts: &[Stmt]) -> Result<Rc<FunctionProto>, Vec<CompileError>> {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+        self.emit_return();
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        let state = self.functions.pop().expect("script function state");
+        Ok(Rc::new(FunctionProto {
+            name: state.name,
+            arity: 0,
+            upvalue_count: 0,
+            chunk: state.chunk,
+        }))
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        limits::deep(|| self.statement_node(stmt))
+    }
+
+    fn statement_node(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.expression(expression);
+                self.emit_op(OpCode::Pop);
+            },
+            Stmt::Print { keyword: _, expression } => {
+                self.expression(expression);
+                self.emit_op(OpCode::Print);
+            },
+            Stmt::Var { name, initializer } => {
+                self.span = name.span;
+                let global = self.declare_variable(name);
+                self.expression(initializer);
+                self.define_variable(global);
+            },
+            Stmt::Block { span: _, statements } => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            },
+            Stmt::IfStmt { predicate, then, els } => {
+                self.expression(predicate);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(then);
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump);
+                self.emit_op(OpCode::Pop);
+                if let Some(els) = els {
+                    self.statement(els);
+                }
+                self.patch_jump(else_jump);
+            },
+            Stmt::WhileStmt { condition, body } => {
+                let label = self.pending_label.take();
+                let loop_start = self.chunk().code.len();
+                self.expression(condition);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.begin_loop(label, Some(loop_start));
+                self.statement(body);
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit_op(OpCode::Pop);
+                self.end_loop();
+            },
+            Stmt::ForStmt { var_decl, expr_stmt, condition, increment, body } => {
+                let label = self.pending_label.take();
+                self.begin_scope();
+                if let Some(var_decl) = var_decl {
+                    self.statement(var_decl);
+                }
+                if let Some(expr_stmt) = expr_stmt {
+                    self.statement(expr_stmt);
+                }
+                let loop_start = self.chunk().code.len();
+                let exit_jump = condition.as_ref().map(|condition| {
+                    self.expression(condition);
+                    let jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit_op(OpCode::Pop);
+                    jump
+                });
+                self.begin_loop(label, increment.is_none().then_some(loop_start));
+                self.statement(body);
+                if let Some(increment) = increment {
+                    let continue_jumps = std::mem::take(&mut self.innermost_loop().continue_jumps);
+                    for jump in continue_jumps {
+                        self.patch_jump(jump);
+                    }
+                    self.expression(increment);
+                    self.emit_op(OpCode::Pop);
+                }
+                self.emit_loop(loop_start);
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump);
+                    self.emit_op(OpCode::Pop);
+                }
+                self.end_loop();
+                self.end_scope();
+            },
+            Stmt::ForInStmt { variable, iterable, body } => {
+                let label = self.pending_label.take();
+                self.span = variable.span;
+                self.begin_scope();
+                // The iterator lives in a hidden local for the duration of the loop.
+                self.expression(iterable);
+                // GetIter and ForIter report errors at the iterable
+                self.span = iterable.span();
+                self.emit_op(OpCode::GetIter);
+                self.add_local(" iterator");
+                self.mark_initialized();
+
+                let loop_start = self.chunk().code.len();
+                let exit_jump = self.emit_jump(OpCode::ForIter);
+                self.begin_loop(label, Some(loop_start));
+                // ForIter pushed the element, which becomes this iteration's variable.
+                self.begin_scope();
+                self.declare_variable(variable);
+                self.mark_initialized();
+                self.statement(body);
+                self.end_scope();
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.end_loop();
+                self.end_scope();
+            },
+            Stmt::Function { name, params, body } => {
+                self.span = name.span;
+                let global = self.declare_variable(name);
+                // A function may refer to itself, so it is usable before its body is compiled.
+                self.mark_initialized();
+                self.function(name, params, body, FunctionKind::Function);
+                self.define_variable(global);
+            },
+            Stmt::Return { keyword, value } => {
+                self.span = keyword.span;
+                let kind = self.current().kind;
+                if kind == FunctionKind::Script {
+                    self.errors.push(CompileError::TopLevelReturn { span: keyword.span });
+                }
+                match value {
+                    Some(value) => {
+                        if kind == FunctionKind::Initializer {
+                            self.errors.push(CompileError::ReturnFromInitializer { span: keyword.span });
+                        }
+                        self.expression(value);
+                        self.emit_op(OpCode::Return);
+                    },
+                    None => self.emit_return(),
+                }
+            },
+            Stmt::Break { keyword, label } => self.loop_jump(keyword, label.as_ref(), true),
+            Stmt::Continue { keyword, label } => self.loop_jump(keyword, label.as_ref(), false),
+            Stmt::Labeled { label, body } => {
+                self.pending_label = Some(label.lexeme.clone());
+                self.statement(body);
+            },
+            Stmt::Class { name, superclass, methods } => self.class(name, superclass.as_ref(), methods),
+        }
+    }
+
+    fn begin_loop(&mut self, label: Option<String>, continue_target: Option<usize>) {
+        let local_count = self.current().locals.len();
+        self.current_mut().loops.push(LoopState {
+            label,
+            local_count,
+            continue_target,
+            continue_jumps: vec![],
+            break_jumps: vec![],
+        });
+    }
+
+    /// Closes the innermost loop, sending its `break`s here.
+    fn end_loop(&mut self) {
+        let state = self.current_mut().loops.pop().expect("loop state");
+        for jump in state.break_jumps {
+            self.patch_jump(jump);
+        }
+    }
+
+    fn innermost_loop(&mut self) -> &mut LoopState {
+        self.current_mut().loops.last_mut().expect("loop state")
+    }
+
+    /// Compiles `break` or `continue`: discards the locals declared inside
+    /// the target loop, then jumps out of it or back to its next iteration.
+    fn loop_jump(&mut self, keyword: &Token, label: Option<&Token>, is_break: bool) {
+        self.span = keyword.span;
+        let loops = &self.current().loops;
+        let target = match label {
+            Some(label) => loops.iter().rposition(|state| state.label.as_deref() == Some(label.lexeme.as_str())),
+            None => loops.len().checked_sub(1),
+        };
+        let Some(target) = target else {
+            match label {
+                Some(label) => {
+                    self.errors.push(CompileError::UnknownLoopLabel { span: label.span, label: label.lexeme.clone() });
+                },
+                None => {
+                    let span = keyword.span;
+                    self.errors.push(CompileError::JumpOutsideLoop { span, keyword: keyword.lexeme.clone() });
+                },
+            }
+            return;
+        };
+
+        // A closure compiled later in the body may still capture one of these
+        // locals on an earlier pass through an inner loop, so close them all.
+        let local_count = self.current().loops[target].local_count;
+        for _ in local_count..self.current().locals.len() {
+            self.emit_op(OpCode::CloseUpvalue);
+        }
+
+        if is_break {
+            let jump = self.emit_jump(OpCode::Jump);
+            self.current_mut().loops[target].break_jumps.push(jump);
+        } else if let Some(loop_start) = self.current().loops[target].continue_target {
+            self.emit_loop(loop_start);
+        } else {
+            let jump = self.emit_jump(OpCode::Jump);
+            self.current_mut().loops[target].continue_jumps.push(jump);
+        }
+    }
+
+    fn class(&mut self, name: &Token, superclass: Option<&Expr>, methods: &[Stmt]) {
+        self.span = name.span;
+        let name_constant = self.identifier_constant(&name.lexeme);
+
+        // Locals get a placeholder slot up front so methods can capture the class by name.
+        let class_slot = if self.current().scope_depth > 0 {
+            self.emit_op(OpCode::Nil);
+            self.declare_variable(name);
+            self.mark_initialized();
+            Some(self.current().locals.len() - 1)
+        } else {
+            None
+        };
+
+        self.classes.push(ClassState { has_superclass: superclass.is_some() });
+
+        if let Some(superclass) = superclass {
+            self.expression(superclass);
+            self.begin_scope();
+            self.add_local("super");
+            self.mark_initialized();
+        }
+
+        let mut method_count = 0;
+        for method in methods {
+            if let Stmt::Function { name: method_name, params, body } = method {
+                let kind = if method_name.lexeme == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                self.function(method_name, params, body, kind);
+                method_count += 1;
+            }
+        }
+        if method_count > u8::MAX as usize {
+            self.too_many(name.span, "Can't have more than 255 methods in one class");
+        }
+
+        if let Some(superclass) = superclass {
+            self.named_variable("super", false);
+            self.span = superclass.span();
+        } else {
+            self.span = name.span;
+        }
+        self.emit_op(OpCode::Class);
+        self.emit_u16(name_constant);
+        self.emit_byte(method_count as u8);
+        self.emit_byte(superclass.is_some() as u8);
+
+        match class_slot {
+            Some(slot) => {
+                self.emit_op(OpCode::SetLocal);
+                self.emit_byte(slot as u8);
+                self.emit_op(OpCode::Pop);
+            },
+            None => {
+                self.emit_op(OpCode::DefineGlobal);
+                self.emit_u16(name_constant);
+            },
+        }
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        self.classes.pop();
+    }
+
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Stmt], kind: FunctionKind) {
+        let mut state = FunctionState::new(&name.lexeme, kind);
+        state.arity = params.len();
+        self.functions.push(state);
+
+        self.begin_scope();
+        for param in params {
+            self.declare_variable(param);
+            self.mark_initialized();
+        }
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.emit_return();
+
+        let state = self.functions.pop().expect("function state");
+        let upvalues = state.upvalues;
+        let index = self.chunk().add_function(FunctionProto {
+            name: state.name,
+            arity: state.arity,
+            upvalue_count: upvalues.len(),
+            chunk: state.chunk,
+        });
+        self.emit_op(OpCode::Closure);
+        let index = self.checked_u16(index, "Too many functions in one chunk");
+        self.emit_u16(index);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        limits::deep(|| self.expression_node(expr))
+    }
+
+    fn expression_node(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { id: _, name, value } => {
+                self.expression(value);
+                self.span = name.span;
+                self.named_variable_token(name, true);
+            },
+            Expr::Binary { left, operator, right } => {
+                self.expression(left);
+                self.expression(right);
+                self.span = operator.span;
+                let op = match operator.token_type {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Percent => OpCode::Modulo,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::DotDot => OpCode::Range,
+                    TokenType::DotDotEqual => OpCode::RangeInclusive,
+                    _ => {
+                        self.invalid_operator(operator);
+                        return;
+                    },
+                };
+                self.emit_op(op);
+            },
+            Expr::Grouping { span: _, expression } => self.expression(expression),
+            Expr::Literal { span: _, value } => match value {
+                LiteralValue::Nil => self.emit_op(OpCode::Nil),
+                LiteralValue::True => self.emit_op(OpCode::True),
+                LiteralValue::False => self.emit_op(OpCode::False),
+                value => {
+                    let index = self.chunk().add_constant(value.clone());
+                    self.emit_op(OpCode::Constant);
+                    let index = self.checked_u16(index, "Too many constants in one chunk");
+                    self.emit_u16(index);
+                },
+            },
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                self.span = operator.span;
+                match operator.token_type {
+                    TokenType::Minus => self.emit_op(OpCode::Negate),
+                    TokenType::Bang => self.emit_op(OpCode::Not),
+                    _ => self.invalid_operator(operator),
+                }
+            },
+            Expr::Variable { id: _, name } => {
+                self.span = name.span;
+                self.named_variable_token(name, false);
+            },
+            Expr::Logical { left, operator, right } => {
+                self.expression(left);
+                self.span = operator.span;
+                match operator.token_type {
+                    TokenType::Or => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        let end_jump = self.emit_jump(OpCode::Jump);
+                        self.patch_jump(else_jump);
+                        self.emit_op(OpCode::Pop);
+                        self.expression(right);
+                        self.patch_jump(end_jump);
+                    },
+                    TokenType::And => {
+                        // A falsy left operand yields `false`, like the tree-walker.
+                        let false_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        self.emit_op(OpCode::Pop);
+                        self.expression(right);
+                        let end_jump = self.emit_jump(OpCode::Jump);
+                        self.patch_jump(false_jump);
+                        self.emit_op(OpCode::Pop);
+                        self.emit_op(OpCode::False);
+                        self.patch_jump(end_jump);
+                    },
+                    _ => self.invalid_operator(operator),
+                }
+            },
+            Expr::Call { calee, paren, arguments } => {
+                self.expression(calee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                self.span = paren.span;
+                if arguments.len() > u8::MAX as usize {
+                    self.too_many(paren.span, "Can't have more than 255 arguments");
+                }
+                self.emit_op(OpCode::Call);
+                self.emit_byte(arguments.len() as u8);
+            },
+            Expr::Get { object, name } => {
+                self.expression(object);
+                self.span = name.span;
+                let constant = self.identifier_constant(&name.lexeme);
+                self.emit_op(OpCode::GetProperty);
+                self.emit_u16(constant);
+            },
+            Expr::Set { object, name, value } => {
+                self.expression(object);
+                self.expression(value);
+                self.span = name.span;
+                let constant = self.identifier_constant(&name.lexeme);
+                self.emit_op(OpCode::SetProperty);
+                self.emit_u16(constant);
+            },
+            Expr::This { id: _, keyword } => {
+                self.span = keyword.span;
+                if self.classes.is_empty() {
+                    self.errors.push(CompileError::ThisOutsideClass { span: keyword.span });
+                    return;
+                }
+                self.named_variable("this", false);
+            },
+            Expr::Super { id: _, keyword, method } => {
+                self.span = keyword.span;
+                match self.classes.last() {
+                    None => self.errors.push(CompileError::SuperOutsideClass { span: keyword.span }),
+                    Some(class) if !class.has_superclass => {
+                        self.errors.push(CompileError::SuperWithoutSuperclass { span: keyword.span })
+                    },
+                    Some(_) => {
+                        let constant = self.identifier_constant(&method.lexeme);
+                        self.named_variable("this", false);
+                        self.named_variable("super", false);
+                        self.emit_op(OpCode::GetSuper);
+                        self.emit_u16(constant);
+                    },
+                }
+            },
+            Expr::List { span: _, bracket, elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.span = bracket.span;
+                if elements.len() > u16::MAX as usize {
+                    self.too_many(bracket.span, "Can't have more than 65535 elements in a list literal");
+                }
+                self.emit_op(OpCode::BuildList);
+                self.emit_u16(elements.len() as u16);
+            },
+            Expr::Map { span: _, brace, entries } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.span = brace.span;
+                if entries.len() > u16::MAX as usize {
+                    self.too_many(brace.span, "Can't have more than 65535 entries in a map literal");
+                }
+                self.emit_op(OpCode::BuildMap);
+                self.emit_u16(entries.len() as u16);
+            },
+            Expr::Index { object, bracket, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.span = bracket.span;
+                self.emit_op(OpCode::GetIndex);
+            },
+            Expr::IndexSet { object, bracket, index, value } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.span = bracket.span;
+                self.emit_op(OpCode::SetIndex);
+            },
+            Expr::Interpolation { span: _, quote, parts } => {
+                for part in parts {
+                    self.expression(part);
+                }
+                self.span = quote.span;
+                if parts.len() > u16::MAX as usize {
+                    self.too_many(quote.span, "Can't have more than 65535 parts in an interpolated string");
+                }
+                self.emit_op(OpCode::BuildString);
+                self.emit_u16(parts.len() as u16);
+            },
+        }
+    }
+
+    fn named_variable_token(&mut self, name: &Token, assign: bool) {
+        let top = self.functions.len() - 1;
+        if let Some(local) = self.resolve_local(top, &name.lexeme) {
+            if self.functions[top].locals[local].depth.is_none() {
+                self.errors.push(CompileError::ReadInOwnInitializer { span: name.span, name: name.lexeme.clone() });
+            }
+        }
+        self.named_variable(&name.lexeme, assign);
+    }
+
+    fn named_variable(&mut self, name: &str, assign: bool) {
+        let top = self.functions.len() - 1;
+        let (get, set, operand) = if let Some(slot) = self.resolve_local(top, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot as u16)
+        } else if let Some(index) = self.resolve_upvalue(top, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index as u16)
+        } else {
+            let constant = self.identifier_constant(name);
+            (OpCode::GetGlobal, OpCode::SetGlobal, constant)
+        };
+
+        let op = if assign { set } else { get };
+        self.emit_op(op);
+        match op {
+            OpCode::GetGlobal | OpCode::SetGlobal => self.emit_u16(operand),
+            _ => self.emit_byte(operand as u8),
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<usize> {
+        self.functions[function].locals.iter().rposition(|local| local.name == name)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<usize> {
+        if function == 0 {
+            return None;
+        }
+        if let Some(local) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[local].is_captured = true;
+            return Some(self.add_upvalue(function, local, true));
+        }
+        let upvalue = self.resolve_upvalue(function - 1, name)?;
+        Some(self.add_upvalue(function, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: usize, is_local: bool) -> usize {
+        let upvalue = UpvalueRef { index: index as u8, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|candidate| *candidate == upvalue) {
+            return existing;
+        }
+        if upvalues.len() >= MAX_UPVALUES {
+            self.too_many(self.span, "Too many closure variables in function");
+            return 0;
+        }
+        upvalues.push(upvalue);
+        upvalues.len() - 1
+    }
+
+    /// Declares `name` in the current scope. Returns the name constant when
+    /// the variable is a global.
+    fn declare_variable(&mut self, name: &Token) -> Option<u16> {
+        if self.current().scope_depth == 0 {
+            return Some(self.identifier_constant(&name.lexeme));
+        }
+
+        let scope_depth = self.current().scope_depth;
+        let duplicate = self.current().locals.iter().rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= scope_depth))
+            .any(|local| local.name == name.lexeme);
+        if duplicate {
+            self.errors.push(CompileError::AlreadyDeclared { span: name.span, name: name.lexeme.clone() });
+        }
+        if self.current().locals.len() >= MAX_LOCALS {
+            self.too_many(name.span, "Too many local variables in function");
+            return None;
+        }
+        self.add_local(&name.lexeme);
+        None
+    }
+
+    fn add_local(&mut self, name: &str) {
+        self.current_mut().locals.push(Local { name: name.to_string(), depth: None, is_captured: false });
+    }
+
+    fn define_variable(&mut self, global: Option<u16>) {
+        match global {
+            Some(constant) => {
+                self.emit_op(OpCode::DefineGlobal);
+                self.emit_u16(constant);
+            },
+            None => self.mark_initialized(),
+        }
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.current_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_mut().scope_depth -= 1;
+        let scope_depth = self.current().scope_depth;
+        while let Some(local) = self.current().locals.last() {
+            if local.depth.is_some_and(|depth| depth <= scope_depth) {
+                break;
+            }
+            let op = if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop };
+            self.current_mut().locals.pop();
+            self.emit_op(op);
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> u16 {
+        let index = self.chunk().add_constant(LiteralValue::StringValue(name.to_string()));
+        self.checked_u16(index, "Too many constants in one chunk")
+    }
+
+    fn checked_u16(&mut self, value: usize, msg: &str) -> u16 {
+        match u16::try_from(value) {
+            Ok(value) => value,
+            Err(_) => {
+                self.too_many(self.span, msg);
+                0
+            },
+        }
+    }
+
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let distance = self.chunk().code.len() - offset - 2;
+        let distance = self.checked_u16(distance, "Too much code to jump over");
+        let [high, low] = distance.to_be_bytes();
+        self.chunk().code[offset] = high;
+        self.chunk().code[offset + 1] = low;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let distance = self.chunk().code.len() - loop_start + 2;
+        let distance = self.checked_u16(distance, "Loop body too large");
+        self.emit_u16(distance);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        let span = self.span;
+        self.chunk().write_op(op, span);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let span = self.span;
+        self.chunk().write(byte, span);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let span = self.span;
+        self.chunk().write_u16(value, span);
+    }
+
+    fn current(&self) -> &FunctionState {
+        self.functions.last().expect("function state")
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("function state")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current_mut().chunk
+    }
+
+    fn too_many(&mut self, span: Span, message: &str) {
+        self.errors.push(CompileError::TooMany { span, message: message.to_string() });
+    }
+
+    fn invalid_operator(&mut self, operator: &Token) {
+        self.errors.push(CompileError::InvalidOperator { span: operator.span, operator: operator.lexeme.clone() });
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,83 @@
+use crate::error::RuntimeError;
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+enum Output {
+    Writer(Box<dyn Write>),
+    Lines(Box<dyn FnMut(&str)>),
+}
+
+enum Input {
+    Stdin,
+    Reader(Box<dyn BufRead>),
+}
+
+/// Where `print` writes and `input()` reads. Defaults to the process's
+/// stdout and stdin; hosts can swap either for their own streams. Clones
+/// share the same streams, and the `with_*` builders give back a console
+/// with a stream of its own, leaving any clones where they were.
+#[derive(Clone)]
+pub struct Console {
+    output: Rc<RefCell<Output>>,
+    input: Rc<RefCell<Input>>,
+}
+
+impl Console {
+    pub fn stdio() -> Self {
+        Self {
+            output: Rc::new(RefCell::new(Output::Writer(Box::new(io::stdout())))),
+            input: Rc::new(RefCell::new(Input::Stdin)),
+        }
+    }
+
+    pub fn with_output(self, output: impl Write + 'static) -> Self {
+        Self { output: Rc::new(RefCell::new(Output::Writer(Box::new(output)))), ..self }
+    }
+
+    /// Hands each printed line to `on_line`, without its newline.
+    pub fn with_output_fn(self, on_line: impl FnMut(&str) + 'static) -> Self {
+        Self { output: Rc::new(RefCell::new(Output::Lines(Box::new(on_line)))), ..self }
+    }
+
+    pub fn with_input(self, input: impl BufRead + 'static) -> Self {
+        Self { input: Rc::new(RefCell::new(Input::Reader(Box::new(input)))), ..self }
+    }
+
+    pub fn print(&self, line: &str) -> Result<(), RuntimeError> {
+        match &mut *self.output.borrow_mut() {
+            Output::Writer(writer) => {
+                writeln!(writer, "{}", line).map_err(|error| format!("Couldn't print: {}", error).into())
+            },
+            Output::Lines(on_line) => {
+                on_line(line);
+                Ok(())
+            },
+        }
+    }
+
+    /// The next line of input without its line ending, or `None` at the
+    /// end of input.
+    pub fn read_line(&self) -> Result<Option<String>, RuntimeError> {
+        let mut line = String::new();
+        let read = match &mut *self.input.borrow_mut() {
+            Input::Stdin => io::stdin().read_line(&mut line),
+            Input::Reader(reader) => reader.read_line(&mut line),
+        };
+        match read {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                let trimmed = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(trimmed);
+                Ok(Some(line))
+            },
+            Err(error) => Err(format!("Couldn't read input: {}", error).into()),
+        }
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::stdio()
+    }
+}
@@ -0,0 +1,176 @@
+use crate::ast::LiteralValue;
+use crate::error::RuntimeError;
+use crate::numeric;
+
+/// Rust types that a script value can be turned into, used for the
+/// arguments of host functions.
+pub trait FromValue: Sized {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError>;
+}
+
+/// Rust types that can be handed to a script as a value.
+pub trait IntoValue {
+    fn into_value(self) -> LiteralValue;
+}
+
+/// What a host function may return: a value, or a value or an error.
+pub trait IntoResult {
+    fn into_result(self) -> Result<LiteralValue, RuntimeError>;
+}
+
+fn mismatch(expected: &str, value: &LiteralValue) -> RuntimeError {
+    format!("Expected {}, got {}", expected, value.to_type()).into()
+}
+
+impl FromValue for LiteralValue {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError> {
+        match value {
+            LiteralValue::Int(x) => Ok(*x),
+            other => Err(mismatch("Int", other)),
+        }
+    }
+}
+
+/// Any number converts, exactly or not.
+impl FromValue for f64 {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError> {
+        match value {
+            LiteralValue::Int(_) | LiteralValue::BigInt(_) | LiteralValue::Rational(_) | LiteralValue::Float(_) => {
+                Ok(numeric::to_f64(value))
+            },
+            other => Err(mismatch("a number", other)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError> {
+        match value {
+            LiteralValue::True => Ok(true),
+            LiteralValue::False => Ok(false),
+            other => Err(mismatch("a boolean", other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError> {
+        match value {
+            LiteralValue::StringValue(s) => Ok(s.clone()),
+            other => Err(mismatch("String", other)),
+        }
+    }
+}
+
+/// `nil` is `None`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError> {
+        match value {
+            LiteralValue::Nil => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+/// Copies the elements out of a list, so changes to the `Vec` don't show in
+/// the script.
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &LiteralValue) -> Result<Self, RuntimeError> {
+        match value {
+            LiteralValue::List(elements) => elements.borrow().iter().map(T::from_value).collect(),
+            other => Err(mismatch("List", other)),
+        }
+    }
+}
+
+impl IntoValue for LiteralValue {
+    fn into_value(self) -> LiteralValue {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::Nil
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::Int(self)
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::Float(self)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::from_bool(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::StringValue(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::StringValue(self.to_string())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> LiteralValue {
+        match self {
+            Some(value) => value.into_value(),
+            None => LiteralValue::Nil,
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> LiteralValue {
+        LiteralValue::list(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: IntoValue> IntoResult for T {
+    fn into_result(self) -> Result<LiteralValue, RuntimeError> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue> IntoResult for Result<T, RuntimeError> {
+    fn into_result(self) -> Result<LiteralValue, RuntimeError> {
+        self.map(IntoValue::into_value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let value = vec![Some(1), None].into_value();
+        assert_eq!(value.to_string(), "[1, nill]");
+        assert_eq!(Vec::<Option<i64>>::from_value(&value).unwrap(), vec![Some(1), None]);
+        assert_eq!(f64::from_value(&LiteralValue::Int(2)).unwrap(), 2.0);
+        assert_eq!(
+            i64::from_value(&"x".into_value()).unwrap_err().message(),
+            "Expected Int, got String"
+        );
+    }
+}
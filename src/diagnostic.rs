@@ -0,0 +1,230 @@
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "ERROR"),
+            Severity::Warning => write!(f, "WARNING"),
+        }
+    }
+}
+
+/// Text attached to a stretch of source. A label on the diagnostic's own
+/// span is drawn with `^`, any other with `-`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A problem found in a program, with enough structure to point at the
+/// source it's about. `code` is a stable identifier for the kind of problem,
+/// like `E0101`; the error types in `error` define them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, span: Span, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            labels: vec![],
+            notes: vec![],
+            help: None,
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Formats the diagnostic for a terminal: a header, the source lines it
+    /// points at with their spans underlined, then notes and help. `name`
+    /// is shown as the file the source came from.
+    pub fn render(&self, name: &str, source: &str, color: bool) -> String {
+        let paint = Paint { color };
+        let lines: Vec<&str> = source.lines().collect();
+
+        // The primary span is always drawn, even when no label names it.
+        let mut marks: Vec<(Span, Option<&str>, bool)> = vec![];
+        if !self.labels.iter().any(|label| label.span == self.span) {
+            marks.push((self.span, None, true));
+        }
+        for label in &self.labels {
+            marks.push((label.span, Some(&label.message), label.span == self.span));
+        }
+        marks.sort_by_key(|(span, _, _)| (span.line, span.column));
+
+        let last_line = marks.iter().map(|(span, _, _)| span.line).max().unwrap_or(self.span.line);
+        let width = last_line.to_string().len();
+        let gutter = |text: &str| paint.blue(&format!("{:>width$} |", text, width = width));
+
+        let mut out = format!(
+            "{}: {}\n{}{} {}:{}:{}\n{}\n",
+            paint.severity(self.severity, &format!("{}[{}]", self.severity, self.code)),
+            paint.bold(&self.message),
+            " ".repeat(width),
+            paint.blue("-->"),
+            name,
+            self.span.line,
+            self.span.column,
+            gutter(""),
+        );
+
+        let mut previous_line = None;
+        for (span, message, primary) in &marks {
+            let text = span.line.checked_sub(1).and_then(|index| lines.get(index)).copied().unwrap_or("");
+            if previous_line != Some(span.line) {
+                if previous_line.is_some_and(|line| span.line > line + 1) {
+                    out.push_str(&format!("{}\n", paint.blue("...")));
+                }
+                out.push_str(&format!("{} {}\n", gutter(&span.line.to_string()), text));
+                previous_line = Some(span.line);
+            }
+            let mut underline = underline(text, span, if *primary { '^' } else { '-' });
+            if let Some(message) = message {
+                underline = format!("{} {}", underline, message);
+            }
+            let underline = if *primary {
+                paint.severity(self.severity, &underline)
+            } else {
+                paint.blue(&underline)
+            };
+            out.push_str(&format!("{} {}{}\n", gutter(""), indent(text, span.column), underline));
+        }
+
+        for note in &self.notes {
+            out.push_str(&format!("{} {}: {}\n", " ".repeat(width), paint.bold("= note"), note));
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("{} {}: {}\n", " ".repeat(width), paint.bold("= help"), help));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+/// Whitespace that lines up with `column` in `text`, keeping tabs so the
+/// terminal expands them the same way it did on the source line.
+fn indent(text: &str, column: usize) -> String {
+    let mut chars = text.chars();
+    (1..column)
+        .map(|_| if chars.next() == Some('\t') { '\t' } else { ' ' })
+        .collect()
+}
+
+/// Marks `span` on its first line. Spans running onto later lines are
+/// underlined to the end of the first one.
+fn underline(text: &str, span: &Span, mark: char) -> String {
+    let length = if span.end_line == span.line {
+        span.end_column.saturating_sub(span.column)
+    } else {
+        (text.chars().count() + 1).saturating_sub(span.column)
+    };
+    mark.to_string().repeat(length.max(1))
+}
+
+/// ANSI styling that does nothing when color is off.
+struct Paint {
+    color: bool,
+}
+
+impl Paint {
+    fn style(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn bold(&self, text: &str) -> String {
+        self.style("1", text)
+    }
+
+    fn blue(&self, text: &str) -> String {
+        self.style("1;34", text)
+    }
+
+    fn severity(&self, severity: Severity, text: &str) -> String {
+        match severity {
+            Severity::Error => self.style("1;31", text),
+            Severity::Warning => self.style("1;33", text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: usize, column: usize, end_column: usize) -> Span {
+        Span { start: 0, end: 0, line, column, end_line: line, end_column }
+    }
+
+    #[test]
+    fn renders_source_with_underlines() {
+        let source = "var a = 1;\nprint a +\n  \"b\";";
+        let diagnostic = Diagnostic::error("E0101", span(2, 9, 10), "Bad operands")
+            .with_label(span(2, 9, 10), "can't add these")
+            .with_label(span(3, 3, 6), "this is a string")
+            .with_help("convert one side first");
+        assert_eq!(
+            diagnostic.render("test.lox", source, false),
+            [
+                "ERROR[E0101]: Bad operands",
+                " --> test.lox:2:9",
+                "  |",
+                "2 | print a +",
+                "  |         ^ can't add these",
+                "3 |   \"b\";",
+                "  |   --- this is a string",
+                "  = help: convert one side first",
+                "",
+            ].join("\n")
+        );
+    }
+
+    #[test]
+    fn unlabeled_span_at_end_of_input_still_gets_a_caret() {
+        let diagnostic = Diagnostic::error("E0101", span(2, 1, 1), "Expected ';'");
+        let rendered = diagnostic.render("test.lox", "print 1\n", true);
+        assert!(rendered.contains("\x1b[1;31mERROR[E0101]\x1b[0m"), "{}", rendered);
+        assert!(rendered.contains("2 |\x1b[0m \n"), "{}", rendered);
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"), "{}", rendered);
+        assert_eq!(diagnostic.to_string(), "Line 2, column 1: Expected ';'");
+    }
+}
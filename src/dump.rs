@@ -0,0 +1,253 @@
+use crate::ast::Expr;
+use crate::limits;
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+
+/// Renders parsed statements as an indented tree, one node per row, each
+/// prefixed with the line its span starts on.
+pub fn outline(stmts: &[Stmt]) -> String {
+    let mut outline = Outline { rows: vec![] };
+    for stmt in stmts {
+        outline.stmt(stmt, 0);
+    }
+    outline.rows.join("\n")
+}
+
+struct Outline {
+    rows: Vec<String>,
+}
+
+impl Outline {
+    fn row(&mut self, line: usize, depth: usize, label: String) {
+        self.rows.push(format!("{:>4} | {}{}", line, "  ".repeat(depth), label));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, depth: usize) {
+        limits::deep(|| self.stmt_node(stmt, depth))
+    }
+
+    fn stmt_node(&mut self, stmt: &Stmt, depth: usize) {
+        let line = stmt.span().line;
+        match stmt {
+            Stmt::Expression { expression } => {
+                self.row(line, depth, "Expression".to_string());
+                self.expr(expression, depth + 1);
+            },
+            Stmt::Print { keyword: _, expression } => {
+                self.row(line, depth, "Print".to_string());
+                self.expr(expression, depth + 1);
+            },
+            Stmt::Var { name, initializer } => {
+                self.row(line, depth, format!("Var {}", name.lexeme));
+                self.expr(initializer, depth + 1);
+            },
+            Stmt::Block { span: _, statements } => {
+                self.row(line, depth, "Block".to_string());
+                for stmt in statements {
+                    self.stmt(stmt, depth + 1);
+                }
+            },
+            Stmt::IfStmt { predicate, then, els } => {
+                self.row(line, depth, "If".to_string());
+                self.expr(predicate, depth + 1);
+                self.stmt(then, depth + 1);
+                if let Some(els) = els {
+                    self.row(line, depth, "Else".to_string());
+                    self.stmt(els, depth + 1);
+                }
+            },
+            Stmt::WhileStmt { condition, body } => {
+                self.row(line, depth, "While".to_string());
+                self.expr(condition, depth + 1);
+                self.stmt(body, depth + 1);
+            },
+            Stmt::ForStmt { var_decl, expr_stmt, condition, increment, body } => {
+                self.row(line, depth, "For".to_string());
+                if let Some(initializer) = var_decl.as_ref().or(expr_stmt.as_ref()) {
+                    self.stmt(initializer, depth + 1);
+                }
+                if let Some(condition) = condition {
+                    self.expr(condition, depth + 1);
+                }
+                if let Some(increment) = increment {
+                    self.expr(increment, depth + 1);
+                }
+                self.stmt(body, depth + 1);
+            },
+            Stmt::ForInStmt { variable, iterable, body } => {
+                self.row(line, depth, format!("ForIn {}", variable.lexeme));
+                self.expr(iterable, depth + 1);
+                self.stmt(body, depth + 1);
+            },
+            Stmt::Function { name, params, body } => {
+                self.row(line, depth, format!("Function {}({})", name.lexeme, lexemes(params)));
+                for stmt in body.iter() {
+                    self.stmt(stmt, depth + 1);
+                }
+            },
+            Stmt::Return { keyword: _, value } => {
+                self.row(line, depth, "Return".to_string());
+                if let Some(value) = value {
+                    self.expr(value, depth + 1);
+                }
+            },
+            Stmt::Break { keyword: _, label } => {
+                let label = label.as_ref().map_or("".to_string(), |label| format!(" {}", label.lexeme));
+                self.row(line, depth, format!("Break{}", label));
+            },
+            Stmt::Continue { keyword: _, label } => {
+                let label = label.as_ref().map_or("".to_string(), |label| format!(" {}", label.lexeme));
+                self.row(line, depth, format!("Continue{}", label));
+            },
+            Stmt::Labeled { label, body } => {
+                self.row(line, depth, format!("Label {}", label.lexeme));
+                self.stmt(body, depth + 1);
+            },
+            Stmt::Class { name, superclass, methods } => {
+                let label = match superclass {
+                    Some(Expr::Variable { id: _, name: superclass }) => {
+                        format!("Class {} < {}", name.lexeme, superclass.lexeme)
+                    },
+                    _ => format!("Class {}", name.lexeme),
+                };
+                self.row(line, depth, label);
+                for method in methods {
+                    self.stmt(method, depth + 1);
+                }
+            },
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, depth: usize) {
+        limits::deep(|| self.expr_node(expr, depth))
+    }
+
+    fn expr_node(&mut self, expr: &Expr, depth: usize) {
+        let line = expr.span().line;
+        match expr {
+            Expr::Assign { id: _, name, value } => {
+                self.row(line, depth, format!("Assign {}", name.lexeme));
+                self.expr(value, depth + 1);
+            },
+            Expr::Binary { left, operator, right } => {
+                self.row(line, depth, format!("Binary {}", operator.lexeme));
+                self.expr(left, depth + 1);
+                self.expr(right, depth + 1);
+            },
+            Expr::Grouping { span: _, expression } => {
+                self.row(line, depth, "Grouping".to_string());
+                self.expr(expression, depth + 1);
+            },
+            Expr::Literal { span: _, value } => self.row(line, depth, format!("Literal {}", value)),
+            Expr::Unary { operator, right } => {
+                self.row(line, depth, format!("Unary {}", operator.lexeme));
+                self.expr(right, depth + 1);
+            },
+            Expr::Variable { id: _, name } => self.row(line, depth, format!("Variable {}", name.lexeme)),
+            Expr::Logical { left, operator, right } => {
+                self.row(line, depth, format!("Logical {}", operator.lexeme));
+                self.expr(left, depth + 1);
+                self.expr(right, depth + 1);
+            },
+            Expr::Call { calee, paren: _, arguments } => {
+                self.row(line, depth, "Call".to_string());
+                self.expr(calee, depth + 1);
+                for argument in arguments {
+                    self.expr(argument, depth + 1);
+                }
+            },
+            Expr::Get { object, name } => {
+                self.row(line, depth, format!("Get {}", name.lexeme));
+                self.expr(object, depth + 1);
+            },
+            Expr::Set { object, name, value } => {
+                self.row(line, depth, format!("Set {}", name.lexeme));
+                self.expr(object, depth + 1);
+                self.expr(value, depth + 1);
+            },
+            Expr::This { id: _, keyword: _ } => self.row(line, depth, "This".to_string()),
+            Expr::Super { id: _, keyword: _, method } => {
+                self.row(line, depth, format!("Super {}", method.lexeme))
+            },
+            Expr::List { span: _, bracket: _, elements } => {
+                self.row(line, depth, "List".to_string());
+                for element in elements {
+                    self.expr(element, depth + 1);
+                }
+            },
+            Expr::Map { span: _, brace: _, entries } => {
+                self.row(line, depth, "Map".to_string());
+                for (key, value) in entries {
+                    self.row(line, depth + 1, "Entry".to_string());
+                    self.expr(key, depth + 2);
+                    self.expr(value, depth + 2);
+                }
+            },
+            Expr::Index { object, bracket: _, index } => {
+                self.row(line, depth, "Index".to_string());
+                self.expr(object, depth + 1);
+                self.expr(index, depth + 1);
+            },
+            Expr::IndexSet { object, bracket: _, index, value } => {
+                self.row(line, depth, "IndexSet".to_string());
+                self.expr(object, depth + 1);
+                self.expr(index, depth + 1);
+                self.expr(value, depth + 1);
+            },
+            Expr::Interpolation { span: _, quote: _, parts } => {
+                self.row(line, depth, "Interpolation".to_string());
+                for part in parts {
+                    self.expr(part, depth + 1);
+                }
+            },
+        }
+    }
+}
+
+fn lexemes(tokens: &[Token]) -> String {
+    tokens.iter().map(|token| token.lexeme.clone()).collect::<Vec<String>>().join(", ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        Parser::new(scanner.tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn sexpr_covers_control_flow() {
+        let stmts = parse("var i = 0;\nwhile (i < 3) { if (i == 1) print i; else i = i + 1; }");
+        assert_eq!(stmts[0].to_string(), "(var i 0)");
+        assert_eq!(
+            stmts[1].to_string(),
+            "(while (< (var i) 3) (block (if (== (var i) 1) (print (var i)) (= i (+ (var i) 1)))))"
+        );
+    }
+
+    #[test]
+    fn outline_indents_children_with_line_numbers() {
+        let stmts = parse("fun add(a, b) {\n  return a + b;\n}\nprint add(1, 2);");
+        assert_eq!(
+            outline(&stmts),
+            [
+                "   1 | Function add(a, b)",
+                "   2 |   Return",
+                "   2 |     Binary +",
+                "   2 |       Variable a",
+                "   2 |       Variable b",
+                "   4 | Print",
+                "   4 |   Call",
+                "   4 |     Variable add",
+                "   4 |     Literal 1",
+                "   4 |     Literal 2",
+            ].join("\n")
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::ast::LiteralValue;
+use crate::console::Console;
+use crate::convert::IntoValue;
+use crate::error::{LoxError, RuntimeError};
+use crate::interpreter::Interpreter;
+use crate::limits::Limits;
+use crate::natives::{HostFunction, NativeFunction};
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+
+
+/// A tree-walking interpreter that keeps its globals between calls to
+/// `eval`, so a host can define values, run scripts against them and read
+/// back what they left behind.
+pub struct Engine {
+    interpreter: Interpreter,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_console(Console::stdio())
+    }
+
+    /// An engine whose scripts print to and read from `console`.
+    pub fn with_console(console: Console) -> Self {
+        Self { interpreter: Interpreter::with_console(console) }
+    }
+
+    /// Bounds the work each later call to `eval` may do.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+    }
+
+    /// Runs `source` and returns the value of its last statement if that is
+    /// an expression statement, and `nil` otherwise. What the resolver
+    /// learned about `source` is dropped afterwards, except for the bodies
+    /// of functions that are still reachable, so a host can call this in a
+    /// loop without memory growing.
+    pub fn eval(&mut self, source: &str) -> Result<LiteralValue, LoxError> {
+        let result = self.run(source);
+        self.interpreter.release();
+        result
+    }
+
+    fn run(&mut self, source: &str) -> Result<LiteralValue, LoxError> {
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens()?;
+        let mut stmts = Parser::new(scanner.tokens).parse()?;
+        Resolver::new(&mut self.interpreter).resolve(&stmts)?;
+
+        let result = match stmts.pop() {
+            Some(Stmt::Expression { expression }) => Some(expression),
+            Some(stmt) => {
+                stmts.push(stmt);
+                None
+            },
+            None => None,
+        };
+        self.interpreter.interpret(stmts.iter().collect())?;
+        match result {
+            Some(expression) => {
+                let value = expression.evaluate(&mut self.interpreter);
+                Ok(value.map_err(|error| error.at(expression.span()))?)
+            },
+            None => Ok(LiteralValue::Nil),
+        }
+    }
+
+    /// Defines `name` as a global, replacing any existing value.
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) {
+        self.interpreter.globals.borrow_mut().define(name.to_string(), value.into_value());
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<LiteralValue> {
+        self.interpreter.globals.borrow().get(name)
+    }
+
+    /// Defines a global function that scripts call with `name(...)`.
+    /// `arity` is `None` to accept any number of arguments.
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        arity: Option<usize>,
+        function: impl Fn(&[LiteralValue]) -> Result<LiteralValue, RuntimeError> + 'static,
+    ) {
+        self.set_global(name, NativeFunction::new(name, arity, function).into_value());
+    }
+
+    /// Like `register_native`, but converts the arguments and result
+    /// between script values and the closure's own types.
+    pub fn register_fn<Args, F: HostFunction<Args> + 'static>(&mut self, name: &str, function: F) {
+        self.set_global(name, NativeFunction::typed(name, function).into_value());
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -45,6 +45,39 @@ impl Environment {
             (None, None) => false
         }
     }
+
+    /// Reads `name` from the environment exactly `distance` hops up the
+    /// `enclosing` chain, as computed by the resolver.
+    pub fn get_at(&self, distance: usize, name: &str) -> Option<LiteralValue> {
+        if distance == 0 {
+            self.values.get(name).cloned()
+        } else {
+            self.enclosing.as_ref()?.borrow().get_at(distance - 1, name)
+        }
+    }
+
+    pub fn assign_at(&mut self, distance: usize, name: &str, value: LiteralValue) -> bool {
+        if distance == 0 {
+            match self.values.get_mut(name) {
+                Some(slot) => {
+                    *slot = value;
+                    true
+                },
+                None => false,
+            }
+        } else {
+            match &self.enclosing {
+                Some(env) => env.borrow_mut().assign_at(distance - 1, name, value),
+                None => false,
+            }
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 
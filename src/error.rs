@@ -0,0 +1,472 @@
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use std::fmt;
+use std::time::Duration;
+
+/// A problem found while turning source text into tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanError {
+    UnrecognizedCharacter { span: Span, character: char },
+    UnterminatedString { span: Span },
+    /// A malformed `\` sequence; `message` says what's wrong with it.
+    InvalidEscape { span: Span, message: String },
+    InvalidNumber { span: Span, text: String },
+    UnterminatedInterpolation { span: Span },
+}
+
+impl ScanError {
+    pub fn span(&self) -> Span {
+        match self {
+            ScanError::UnrecognizedCharacter { span, .. }
+            | ScanError::UnterminatedString { span }
+            | ScanError::InvalidEscape { span, .. }
+            | ScanError::InvalidNumber { span, .. }
+            | ScanError::UnterminatedInterpolation { span } => *span,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScanError::UnrecognizedCharacter { .. } => "E0001",
+            ScanError::UnterminatedString { .. } => "E0002",
+            ScanError::InvalidEscape { .. } => "E0003",
+            ScanError::InvalidNumber { .. } => "E0004",
+            ScanError::UnterminatedInterpolation { .. } => "E0005",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ScanError::UnrecognizedCharacter { character, .. } => {
+                format!("Unrecognized character '{}'", character.escape_debug())
+            },
+            ScanError::UnterminatedString { .. } => "Unterminated string".to_string(),
+            ScanError::InvalidEscape { message, .. } => message.clone(),
+            ScanError::InvalidNumber { text, .. } => format!("Could not parse number {}", text),
+            ScanError::UnterminatedInterpolation { .. } => "Unterminated string interpolation".to_string(),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code(), self.span(), self.message());
+        match self {
+            ScanError::UnterminatedString { .. } => diagnostic.with_help("add a closing '\"'"),
+            ScanError::InvalidEscape { .. } => diagnostic
+                .with_help("strings understand \\n \\t \\r \\0 \\\\ \\\" \\$ and \\u{...} with 1 to 6 hex digits"),
+            ScanError::UnterminatedInterpolation { .. } => {
+                diagnostic.with_help("close the embedded expression with '}' and the string with '\"'")
+            },
+            _ => diagnostic,
+        }
+    }
+}
+
+/// A problem found while parsing tokens into statements.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The grammar wanted something else here. `found` is the lexeme that
+    /// was there instead, or `None` at the end of input.
+    ExpectedToken { span: Span, message: String, found: Option<String> },
+    ExpectedExpression { span: Span, found: Option<String> },
+    /// `=` after something that can't be assigned to, which is at `target`.
+    InvalidAssignmentTarget { span: Span, target: Span },
+    TooMany { span: Span, limit: usize, what: &'static str },
+    /// `class A < A`; `class` is where the class's own name is.
+    InheritsFromItself { span: Span, class: Span },
+    /// A label in front of a statement that isn't a loop, which starts at `statement`.
+    LabelOnNonLoop { span: Span, statement: Span },
+    /// A literal token without the value the scanner gives it, which only
+    /// happens when tokens are built by hand.
+    InvalidLiteral { span: Span, lexeme: String },
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::ExpectedToken { span, .. }
+            | ParseError::ExpectedExpression { span, .. }
+            | ParseError::InvalidAssignmentTarget { span, .. }
+            | ParseError::TooMany { span, .. }
+            | ParseError::InheritsFromItself { span, .. }
+            | ParseError::LabelOnNonLoop { span, .. }
+            | ParseError::InvalidLiteral { span, .. } => *span,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::ExpectedToken { .. } => "E0101",
+            ParseError::ExpectedExpression { .. } => "E0102",
+            ParseError::InvalidAssignmentTarget { .. } => "E0103",
+            ParseError::TooMany { .. } => "E0104",
+            ParseError::InheritsFromItself { .. } => "E0105",
+            ParseError::LabelOnNonLoop { .. } => "E0106",
+            ParseError::InvalidLiteral { .. } => "E0107",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::ExpectedToken { message, .. } => message.clone(),
+            ParseError::ExpectedExpression { .. } => "Expected expression".to_string(),
+            ParseError::InvalidAssignmentTarget { .. } => "Invalid assignment target.".to_string(),
+            ParseError::TooMany { limit, what, .. } => format!("Can't have more than {} {}", limit, what),
+            ParseError::InheritsFromItself { .. } => "A class can't inherit from itself".to_string(),
+            ParseError::LabelOnNonLoop { .. } => "Only loops can be labeled".to_string(),
+            ParseError::InvalidLiteral { lexeme, .. } => format!("Invalid literal '{}'", lexeme),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code(), self.span(), self.message());
+        match self {
+            ParseError::ExpectedToken { span, found, .. } | ParseError::ExpectedExpression { span, found } => {
+                let found = match found {
+                    Some(lexeme) => format!("found '{}'", lexeme),
+                    None => "found end of input".to_string(),
+                };
+                diagnostic.with_label(*span, found)
+            },
+            ParseError::InvalidAssignmentTarget { target, .. } => {
+                diagnostic.with_label(*target, "can't assign to this")
+            },
+            ParseError::InheritsFromItself { class, .. } => diagnostic.with_label(*class, "class declared here"),
+            ParseError::LabelOnNonLoop { statement, .. } => {
+                diagnostic.with_label(*statement, "expected 'while' or 'for' here")
+            },
+            ParseError::TooMany { .. } | ParseError::InvalidLiteral { .. } => diagnostic,
+        }
+    }
+}
+
+/// A problem found in a parsed program before it runs: by the resolver on
+/// the tree-walker, by the compiler on the VM. Both report the same checks
+/// the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    ReadInOwnInitializer { span: Span, name: String },
+    AlreadyDeclared { span: Span, name: String },
+    TopLevelReturn { span: Span },
+    ReturnFromInitializer { span: Span },
+    ThisOutsideClass { span: Span },
+    SuperOutsideClass { span: Span },
+    SuperWithoutSuperclass { span: Span },
+    /// `break` or `continue`, named by `keyword`, with no loop around it.
+    JumpOutsideLoop { span: Span, keyword: String },
+    UnknownLoopLabel { span: Span, label: String },
+    /// A program too big for the bytecode format; `message` says which limit.
+    TooMany { span: Span, message: String },
+    InvalidOperator { span: Span, operator: String },
+}
+
+impl CompileError {
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::ReadInOwnInitializer { span, .. }
+            | CompileError::AlreadyDeclared { span, .. }
+            | CompileError::TopLevelReturn { span }
+            | CompileError::ReturnFromInitializer { span }
+            | CompileError::ThisOutsideClass { span }
+            | CompileError::SuperOutsideClass { span }
+            | CompileError::SuperWithoutSuperclass { span }
+            | CompileError::JumpOutsideLoop { span, .. }
+            | CompileError::UnknownLoopLabel { span, .. }
+            | CompileError::TooMany { span, .. }
+            | CompileError::InvalidOperator { span, .. } => *span,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::ReadInOwnInitializer { .. } => "E0201",
+            CompileError::AlreadyDeclared { .. } => "E0202",
+            CompileError::TopLevelReturn { .. } => "E0203",
+            CompileError::ReturnFromInitializer { .. } => "E0204",
+            CompileError::ThisOutsideClass { .. } => "E0205",
+            CompileError::SuperOutsideClass { .. } => "E0206",
+            CompileError::SuperWithoutSuperclass { .. } => "E0207",
+            CompileError::JumpOutsideLoop { .. } => "E0208",
+            CompileError::UnknownLoopLabel { .. } => "E0209",
+            CompileError::InvalidOperator { .. } => "E0210",
+            CompileError::TooMany { .. } => "E0211",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            CompileError::ReadInOwnInitializer { .. } => "Can't read local variable in its own initializer".to_string(),
+            CompileError::AlreadyDeclared { .. } => "Already a variable with this name in this scope".to_string(),
+            CompileError::TopLevelReturn { .. } => "Can't return from top-level code".to_string(),
+            CompileError::ReturnFromInitializer { .. } => "Can't return a value from an initializer".to_string(),
+            CompileError::ThisOutsideClass { .. } => "Can't use 'this' outside of a class".to_string(),
+            CompileError::SuperOutsideClass { .. } => "Can't use 'super' outside of a class".to_string(),
+            CompileError::SuperWithoutSuperclass { .. } => {
+                "Can't use 'super' in a class with no superclass".to_string()
+            },
+            CompileError::JumpOutsideLoop { keyword, .. } => format!("Can't use '{}' outside of a loop", keyword),
+            CompileError::UnknownLoopLabel { label, .. } => format!("No enclosing loop labeled '{}'", label),
+            CompileError::TooMany { message, .. } => message.clone(),
+            CompileError::InvalidOperator { operator, .. } => format!("Invalid operator '{}'", operator),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.code(), self.span(), self.message());
+        match self {
+            CompileError::ReturnFromInitializer { .. } => {
+                diagnostic.with_help("an initializer always returns 'this'; use a bare 'return;'")
+            },
+            _ => diagnostic,
+        }
+    }
+}
+
+/// Something that went wrong while a program ran, on either backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable { span: Span, name: String },
+    /// A call to a value of type `type_name`, which isn't a function or class.
+    NotCallable { span: Span, type_name: String },
+    WrongArity { span: Span, callee: String, expected: usize, got: usize },
+    /// More than `limit` calls in progress at once.
+    StackOverflow { span: Span, limit: usize },
+    StepLimitExceeded { span: Span, limit: u64 },
+    TimeLimitExceeded { span: Span, limit: Duration },
+    /// An operation turned down its operands: wrong types, division by
+    /// zero, an index out of bounds, a missing property and the like.
+    InvalidOperation { span: Span, message: String },
+}
+
+impl RuntimeError {
+    /// An `InvalidOperation` at `span`, for the messages that operations on
+    /// values report.
+    pub fn invalid(span: Span, message: String) -> Self {
+        RuntimeError::InvalidOperation { span, message }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::UndefinedVariable { span, .. }
+            | RuntimeError::NotCallable { span, .. }
+            | RuntimeError::WrongArity { span, .. }
+            | RuntimeError::StackOverflow { span, .. }
+            | RuntimeError::StepLimitExceeded { span, .. }
+            | RuntimeError::TimeLimitExceeded { span, .. }
+            | RuntimeError::InvalidOperation { span, .. } => *span,
+        }
+    }
+
+    /// Gives an error raised without knowing where, such as one converted
+    /// from a plain message, the span of the code that was running.
+    pub fn at(mut self, location: Span) -> Self {
+        match &mut self {
+            RuntimeError::UndefinedVariable { span, .. }
+            | RuntimeError::NotCallable { span, .. }
+            | RuntimeError::WrongArity { span, .. }
+            | RuntimeError::StackOverflow { span, .. }
+            | RuntimeError::StepLimitExceeded { span, .. }
+            | RuntimeError::TimeLimitExceeded { span, .. }
+            | RuntimeError::InvalidOperation { span, .. } => {
+                if *span == Span::default() {
+                    *span = location;
+                }
+            },
+        }
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::UndefinedVariable { .. } => "E0301",
+            RuntimeError::NotCallable { .. } => "E0302",
+            RuntimeError::WrongArity { .. } => "E0303",
+            RuntimeError::StackOverflow { .. } => "E0304",
+            RuntimeError::InvalidOperation { .. } => "E0305",
+            RuntimeError::StepLimitExceeded { .. } => "E0306",
+            RuntimeError::TimeLimitExceeded { .. } => "E0307",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::UndefinedVariable { name, .. } => format!("Variable '{}' has not been declared", name),
+            RuntimeError::NotCallable { type_name, .. } => {
+                format!("{} is not callable, can only call functions and classes", type_name)
+            },
+            RuntimeError::WrongArity { callee, expected, got, .. } => {
+                format!("Expected {} arguments but got {} when calling '{}'", expected, got, callee)
+            },
+            RuntimeError::StackOverflow { limit, .. } => format!("Stack overflow: more than {} nested calls", limit),
+            RuntimeError::StepLimitExceeded { limit, .. } => format!("Step limit of {} exceeded", limit),
+            RuntimeError::TimeLimitExceeded { limit, .. } => format!("Time limit of {:?} exceeded", limit),
+            RuntimeError::InvalidOperation { message, .. } => message.clone(),
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.code(), self.span(), self.message())
+    }
+}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        RuntimeError::InvalidOperation { span: Span::default(), message }
+    }
+}
+
+macro_rules! impl_error {
+    ($($error:ty),*) => {$(
+        impl fmt::Display for $error {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}: {}", self.span(), self.message())
+            }
+        }
+
+        impl std::error::Error for $error {}
+    )*};
+}
+
+impl_error!(ScanError, ParseError, CompileError, RuntimeError);
+
+/// Any error from running a program. Everything but `Runtime` is found
+/// before the program starts, and each of those variants holds every
+/// problem its phase ran into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    Scan(Vec<ScanError>),
+    Parse(Vec<ParseError>),
+    Compile(Vec<CompileError>),
+    Runtime(RuntimeError),
+}
+
+impl LoxError {
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            LoxError::Scan(errors) => errors.iter().map(ScanError::diagnostic).collect(),
+            LoxError::Parse(errors) => errors.iter().map(ParseError::diagnostic).collect(),
+            LoxError::Compile(errors) => errors.iter().map(CompileError::diagnostic).collect(),
+            LoxError::Runtime(error) => vec![error.diagnostic()],
+        }
+    }
+
+    /// The process exit status for this error, following the reference
+    /// Lox: 65 for a program that doesn't compile, 70 for one that fails
+    /// while running.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoxError::Scan(_) | LoxError::Parse(_) | LoxError::Compile(_) => 65,
+            LoxError::Runtime(_) => 70,
+        }
+    }
+
+    /// Formats every problem for a terminal, quoting the lines of `source`
+    /// they point at. `name` says where that source came from.
+    pub fn render(&self, name: &str, source: &str, color: bool) -> String {
+        self.diagnostics().iter()
+            .map(|diagnostic| diagnostic.render(name, source, color))
+            .collect::<Vec<String>>()
+            .join("\n")
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = match self {
+            LoxError::Scan(errors) => errors.iter().map(ToString::to_string).collect(),
+            LoxError::Parse(errors) => errors.iter().map(ToString::to_string).collect(),
+            LoxError::Compile(errors) => errors.iter().map(ToString::to_string).collect(),
+            LoxError::Runtime(error) => vec![error.to_string()],
+        };
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+impl From<Vec<ScanError>> for LoxError {
+    fn from(errors: Vec<ScanError>) -> Self {
+        LoxError::Scan(errors)
+    }
+}
+
+impl From<Vec<ParseError>> for LoxError {
+    fn from(errors: Vec<ParseError>) -> Self {
+        LoxError::Parse(errors)
+    }
+}
+
+impl From<Vec<CompileError>> for LoxError {
+    fn from(errors: Vec<CompileError>) -> Self {
+        LoxError::Compile(errors)
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(error: RuntimeError) -> Self {
+        LoxError::Runtime(error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_map_to_exit_codes_and_codes() {
+        let span = Span { start: 0, end: 1, line: 1, column: 1, end_line: 1, end_column: 2 };
+        let scan = LoxError::from(vec![ScanError::UnterminatedString { span }]);
+        let runtime = LoxError::from(RuntimeError::StackOverflow { span, limit: 1024 });
+        assert_eq!(scan.exit_code(), 65);
+        assert_eq!(runtime.exit_code(), 70);
+        assert_eq!(scan.diagnostics()[0].code, "E0002");
+        assert_eq!(runtime.to_string(), "Line 1, column 1: Stack overflow: more than 1024 nested calls");
+
+        // `at` only fills in a span that's missing
+        let other = Span { line: 2, ..span };
+        let unlocated = RuntimeError::from("Division by zero".to_string());
+        assert_eq!(unlocated.at(span).at(other).span(), span);
+    }
+
+    #[test]
+    fn every_error_kind_has_its_own_code() {
+        let span = Span::default();
+        let text = String::new;
+        let codes = [
+            ScanError::UnrecognizedCharacter { span, character: '#' }.code(),
+            ScanError::UnterminatedString { span }.code(),
+            ScanError::InvalidEscape { span, message: text() }.code(),
+            ScanError::InvalidNumber { span, text: text() }.code(),
+            ScanError::UnterminatedInterpolation { span }.code(),
+            ParseError::ExpectedToken { span, message: text(), found: None }.code(),
+            ParseError::ExpectedExpression { span, found: None }.code(),
+            ParseError::InvalidAssignmentTarget { span, target: span }.code(),
+            ParseError::TooMany { span, limit: 255, what: "arguments" }.code(),
+            ParseError::InheritsFromItself { span, class: span }.code(),
+            ParseError::LabelOnNonLoop { span, statement: span }.code(),
+            ParseError::InvalidLiteral { span, lexeme: text() }.code(),
+            CompileError::ReadInOwnInitializer { span, name: text() }.code(),
+            CompileError::AlreadyDeclared { span, name: text() }.code(),
+            CompileError::TopLevelReturn { span }.code(),
+            CompileError::ReturnFromInitializer { span }.code(),
+            CompileError::ThisOutsideClass { span }.code(),
+            CompileError::SuperOutsideClass { span }.code(),
+            CompileError::SuperWithoutSuperclass { span }.code(),
+            CompileError::JumpOutsideLoop { span, keyword: text() }.code(),
+            CompileError::UnknownLoopLabel { span, label: text() }.code(),
+            CompileError::InvalidOperator { span, operator: text() }.code(),
+            CompileError::TooMany { span, message: text() }.code(),
+            RuntimeError::UndefinedVariable { span, name: text() }.code(),
+            RuntimeError::NotCallable { span, type_name: text() }.code(),
+            RuntimeError::WrongArity { span, callee: text(), expected: 0, got: 1 }.code(),
+            RuntimeError::StackOverflow { span, limit: 1 }.code(),
+            RuntimeError::StepLimitExceeded { span, limit: 1 }.code(),
+            RuntimeError::TimeLimitExceeded { span, limit: Duration::ZERO }.code(),
+            RuntimeError::InvalidOperation { span, message: text() }.code(),
+        ];
+        let unique: std::collections::HashSet<&str> = codes.iter().copied().collect();
+        assert_eq!(unique.len(), codes.len(), "{:?}", codes);
+    }
+}
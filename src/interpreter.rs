@@ -1,77 +1,881 @@
-use crate::ast::LiteralValue;
+use crate::ast::{Expr, LiteralValue};
+use crate::callable::{Callable, LoxFunction};
+use crate::class::LoxClass;
+use crate::console::Console;
+use crate::error::RuntimeError;
+use crate::limits::{self, Budget, Limits};
 use crate::stmt::Stmt;
 use crate::environment::Environment;
-use std::rc::Rc;
+use crate::natives;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 
 
 pub struct Interpreter {
-    environment: Rc<RefCell<Environment>>,
+    pub globals: Rc<RefCell<Environment>>,
+    pub environment: Rc<RefCell<Environment>>,
+    locals: HashMap<usize, usize>,
+    /// The ids in `locals` from code outside any function, which `release`
+    /// drops, and those from each function body, which it drops once the
+    /// body is gone.
+    top_level_ids: Vec<usize>,
+    body_ids: Vec<(Weak<Vec<Stmt>>, Vec<usize>)>,
+    console: Console,
+    budget: Budget,
+    /// Calls in progress.
+    depth: usize,
+}
+
+/// Anything that stops the statements of a block from running to completion.
+/// `Return` carries the returned value up to the enclosing call; `Break` and
+/// `Continue` travel up to the loop they name, or the innermost one.
+pub enum Unwind {
+    Error(RuntimeError),
+    Return(LiteralValue),
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_console(Console::stdio())
+    }
+
+    pub fn with_console(console: Console) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        for (name, native) in natives::globals(&console) {
+            globals.borrow_mut().define(name, native);
+        }
         Self {
-            environment: Rc::new(RefCell::new(Environment::new())),
+            globals: globals.clone(),
+            environment: globals,
+            locals: HashMap::new(),
+            top_level_ids: vec![],
+            body_ids: vec![],
+            console,
+            budget: Budget::new(Limits::default()),
+            depth: 0,
+        }
+    }
+
+    /// Applies to every later call to `interpret`; time counts from the
+    /// start of each one.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Budget::new(limits);
+    }
+
+    /// Records that the expression with `id` refers to a variable declared
+    /// `depth` scopes out from where it is used.
+    pub fn resolve(&mut self, id: usize, depth: usize) {
+        self.locals.insert(id, depth);
+    }
+
+    /// Ties ids already passed to `resolve` to the function `body` they
+    /// appear in, or with `None` to the top-level statements being run.
+    pub fn own_resolved(&mut self, body: Option<&Rc<Vec<Stmt>>>, ids: Vec<usize>) {
+        match body {
+            Some(body) => self.body_ids.push((Rc::downgrade(body), ids)),
+            None => self.top_level_ids.extend(ids),
         }
     }
 
-    pub fn interpret(&mut self, stmts: Vec<&Stmt>) -> Result<(), String> {
+    /// Forgets the resolved ids of top-level statements and of function
+    /// bodies nothing refers to any more. Resolution data otherwise lives as
+    /// long as the interpreter, so hosts that keep running new statements
+    /// call this once each batch has finished.
+    pub fn release(&mut self) {
+        for id in self.top_level_ids.drain(..) {
+            self.locals.remove(&id);
+        }
+        let locals = &mut self.locals;
+        self.body_ids.retain(|(body, ids)| {
+            let alive = body.strong_count() > 0;
+            if !alive {
+                for id in ids {
+                    locals.remove(id);
+                }
+            }
+            alive
+        });
+    }
+
+    pub fn depth_of(&self, id: usize) -> Option<usize> {
+        self.locals.get(&id).copied()
+    }
+
+    /// Unresolved names are globals.
+    pub fn look_up_variable(&self, id: usize, name: &str) -> Option<LiteralValue> {
+        match self.depth_of(id) {
+            Some(depth) => self.environment.borrow().get_at(depth, name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    pub fn assign_variable(&mut self, id: usize, name: &str, value: LiteralValue) -> bool {
+        match self.depth_of(id) {
+            Some(depth) => self.environment.borrow_mut().assign_at(depth, name, value),
+            None => self.globals.borrow_mut().assign(name, value),
+        }
+    }
+
+    pub fn interpret(&mut self, stmts: Vec<&Stmt>) -> Result<(), RuntimeError> {
+        self.budget = Budget::new(self.budget.limits());
         for stmt in stmts {
-            match stmt {
-                Stmt::Expression { expression } => {
-                    expression.evaluate(self.environment.clone())?;
-                },
-                Stmt::Print { expression } => {
-                    let value = expression.evaluate(self.environment.clone())?;
-                    println!("{}", value.to_string())
+            let error = match self.execute(stmt) {
+                Ok(()) => continue,
+                Err(Unwind::Error(error)) => error,
+                Err(Unwind::Return(_)) => "Can't return from top-level code".to_string().into(),
+                Err(Unwind::Break(_) | Unwind::Continue(_)) => {
+                    "Can't use 'break' or 'continue' outside of a loop".to_string().into()
                 },
-                Stmt::Var { name, initializer } => {
-                    let value = initializer.evaluate(self.environment.clone())?;
+            };
+            return Err(error.at(stmt.span()));
+        }
+        Ok(())
+    }
 
-                    self.environment.borrow_mut().define(name.lexeme.clone(), value);
-                },
-                Stmt::Block { statements } => {
-                    let mut new_environment = Environment::new();
-                    new_environment.enclosing = Some(self.environment.clone());
-                    let old_environment = self.environment.clone();
-                    self.environment = Rc::new(RefCell::new(new_environment));
-                    let block_result = self.interpret((*statements)
-                        .iter()
-                        .map(|b| b.as_ref())
-                        .collect());
-                    self.environment = old_environment;
-                    block_result?;
-                },
-                Stmt::IfStmt { predicate, then, els } => {
-                    let truth_value = predicate.evaluate(self.environment.clone())?;
-                    if truth_value.is_truthy() == LiteralValue::True {
-                        let statements = vec![then.as_ref()];
-                        self.interpret(statements)?;
-                    } else if let Some(els_stmt) = els {
-                        let statements = vec![els_stmt.as_ref()];
-                        self.interpret(statements)?;
+    /// Runs `statements` inside `environment`, restoring the current
+    /// environment afterwards even if a statement errors or returns.
+    pub fn execute_block(&mut self, statements: &[Stmt], environment: Environment) -> Result<(), Unwind> {
+        let old_environment = self.environment.clone();
+        self.environment = Rc::new(RefCell::new(environment));
+        let block_result = statements.iter().try_for_each(|stmt| self.execute(stmt));
+        self.environment = old_environment;
+        block_result
+    }
+
+    /// Runs a `while`, `for` or `for-in` statement. `label` is the name the
+    /// loop was given with `label: ...`, if any.
+    fn execute_loop(&mut self, stmt: &Stmt, label: Option<&str>) -> Result<(), Unwind> {
+        match stmt {
+            Stmt::WhileStmt { condition, body } => {
+                while condition.evaluate(self)?.is_truthy() == LiteralValue::True {
+                    if !loop_control(self.execute(body), label)? {
+                        break;
                     }
-                },
-                Stmt::WhileStmt { condition, body } => {
-                    let mut flag = condition.evaluate(self.environment.clone())?;
-                    while flag.is_truthy() == LiteralValue::True {
-                        let statements = vec![body.as_ref()];
-                        self.interpret(statements)?;
-                        flag = condition.evaluate(self.environment.clone())?;
+                }
+            },
+            Stmt::ForStmt { var_decl, expr_stmt, condition, increment, body } => {
+                let mut environment = Environment::new();
+                environment.enclosing = Some(self.environment.clone());
+                let old_environment = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+                let loop_result = self.execute_for(
+                    var_decl.as_deref().or(expr_stmt.as_deref()),
+                    condition.as_ref(),
+                    increment.as_ref(),
+                    body,
+                    label,
+                );
+                self.environment = old_environment;
+                loop_result?;
+            },
+            Stmt::ForInStmt { variable, iterable, body } => {
+                let locate = |msg| RuntimeError::invalid(iterable.span(), msg);
+                let iterator = iterable.evaluate(self)?.iterate().map_err(locate)?;
+                loop {
+                    let next = iterator.borrow_mut().next().map_err(locate)?;
+                    let Some(value) = next else { break };
+                    // A fresh scope per iteration, so closures capture that iteration's value.
+                    let mut environment = Environment::new();
+                    environment.enclosing = Some(self.environment.clone());
+                    environment.define(variable.lexeme.clone(), value);
+                    if !loop_control(self.execute_block(std::slice::from_ref(body), environment), label)? {
+                        break;
                     }
-                },
-                Stmt::ForStmt {
-                    var_decl: _,
-                    expr_stmt: _,
-                    condition: _,
-                    increment:_ ,
-                    body: _
-                } => {
-                    todo!()
                 }
-            };
+            },
+            other => return self.execute(other),
+        }
+        Ok(())
+    }
+
+    /// The C-style `for`, run inside the scope holding its initializer.
+    fn execute_for(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        increment: Option<&Expr>,
+        body: &Stmt,
+        label: Option<&str>,
+    ) -> Result<(), Unwind> {
+        if let Some(initializer) = initializer {
+            self.execute(initializer)?;
+        }
+        loop {
+            if let Some(condition) = condition {
+                if condition.evaluate(self)?.is_truthy() == LiteralValue::False {
+                    return Ok(());
+                }
+            }
+            if !loop_control(self.execute(body), label)? {
+                return Ok(());
+            }
+            if let Some(increment) = increment {
+                increment.evaluate(self)?;
+            }
         }
+    }
+
+    /// Runs `f` as a call nested one level deeper than the current code.
+    pub fn call_nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        self.depth += 1;
+        let result = self.budget.enter(self.depth).and_then(|_| f(self));
+        self.depth -= 1;
+        result
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        self.budget.step().map_err(|error| error.at(stmt.span()))?;
+        limits::deep(|| self.execute_node(stmt))
+    }
+
+    fn execute_node(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        match stmt {
+            Stmt::Expression { expression } => {
+                expression.evaluate(self)?;
+            },
+            Stmt::Print { keyword: _, expression } => {
+                let value = expression.evaluate(self)?;
+                self.console.print(&value.to_string())?;
+            },
+            Stmt::Var { name, initializer } => {
+                let value = initializer.evaluate(self)?;
+
+                self.environment.borrow_mut().define(name.lexeme.clone(), value);
+            },
+            Stmt::Block { span: _, statements } => {
+                let mut new_environment = Environment::new();
+                new_environment.enclosing = Some(self.environment.clone());
+                self.execute_block(statements, new_environment)?;
+            },
+            Stmt::IfStmt { predicate, then, els } => {
+                let truth_value = predicate.evaluate(self)?;
+                if truth_value.is_truthy() == LiteralValue::True {
+                    self.execute(then)?;
+                } else if let Some(els_stmt) = els {
+                    self.execute(els_stmt)?;
+                }
+            },
+            Stmt::WhileStmt { .. } | Stmt::ForStmt { .. } | Stmt::ForInStmt { .. } => {
+                self.execute_loop(stmt, None)?;
+            },
+            Stmt::Labeled { label, body } => self.execute_loop(body, Some(&label.lexeme))?,
+            Stmt::Break { keyword: _, label } => {
+                return Err(Unwind::Break(label.as_ref().map(|label| label.lexeme.clone())));
+            },
+            Stmt::Continue { keyword: _, label } => {
+                return Err(Unwind::Continue(label.as_ref().map(|label| label.lexeme.clone())));
+            },
+            Stmt::Function { name, params, body } => {
+                let function = LoxFunction {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: body.clone(),
+                    closure: self.environment.clone(),
+                    is_initializer: false,
+                };
+                let callable = LiteralValue::Callable(Callable::Function(Rc::new(function)));
+                self.environment.borrow_mut().define(name.lexeme.clone(), callable);
+            },
+            Stmt::Return { keyword: _, value } => {
+                let value = match value {
+                    Some(expr) => expr.evaluate(self)?,
+                    None => LiteralValue::Nil,
+                };
+                return Err(Unwind::Return(value));
+            },
+            Stmt::Class { name, superclass, methods } => {
+                let superclass = match superclass {
+                    Some(expr) => match expr.evaluate(self)? {
+                        LiteralValue::Callable(Callable::Class(class)) => Some(class),
+                        other => return Err(Unwind::Error(RuntimeError::invalid(expr.span(), format!(
+                            "Superclass of '{}' must be a class, got {}", name.lexeme, other.to_type()
+                        )))),
+                    },
+                    None => None,
+                };
+
+                // Methods of a subclass close over an extra scope that binds `super`.
+                let method_closure = match &superclass {
+                    Some(class) => {
+                        let mut environment = Environment::new();
+                        environment.enclosing = Some(self.environment.clone());
+                        environment.define("super".to_string(), LiteralValue::Callable(Callable::Class(class.clone())));
+                        Rc::new(RefCell::new(environment))
+                    },
+                    None => self.environment.clone(),
+                };
+
+                let mut class_methods = HashMap::new();
+                for method in methods {
+                    if let Stmt::Function { name: method_name, params, body } = method {
+                        let function = LoxFunction {
+                            name: method_name.clone(),
+                            params: params.clone(),
+                            body: body.clone(),
+                            closure: method_closure.clone(),
+                            is_initializer: method_name.lexeme == "init",
+                        };
+                        class_methods.insert(method_name.lexeme.clone(), Callable::Function(Rc::new(function)));
+                    }
+                }
+                let class = LoxClass {
+                    name: name.lexeme.clone(),
+                    superclass,
+                    methods: class_methods,
+                };
+                let callable = LiteralValue::Callable(Callable::Class(Rc::new(class)));
+                self.environment.borrow_mut().define(name.lexeme.clone(), callable);
+            },
+        };
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decides what a loop does after its body ran: `Ok(true)` to go on with the
+/// next iteration, `Ok(false)` to leave the loop. A `break` or `continue`
+/// aimed at an outer loop keeps unwinding.
+fn loop_control(result: Result<(), Unwind>, label: Option<&str>) -> Result<bool, Unwind> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(Unwind::Break(target)) if target.is_none() || target.as_deref() == label => Ok(false),
+        Err(Unwind::Continue(target)) if target.is_none() || target.as_deref() == label => Ok(true),
+        Err(unwind) => Err(unwind),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LoxError;
+    use crate::scanner::Scanner;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use proptest::prelude::*;
+
+    fn run(source: &str) -> Result<Interpreter, String> {
+        run_with_limits(source, Limits::default())
+    }
+
+    fn run_with_limits(source: &str, limits: Limits) -> Result<Interpreter, String> {
+        let run = || -> Result<Interpreter, LoxError> {
+            let mut scanner = Scanner::new(source);
+            scanner.scan_tokens()?;
+            let mut parser = Parser::new(scanner.tokens);
+            let stmts = parser.parse()?;
+            let mut interpreter = Interpreter::new();
+            interpreter.set_limits(limits);
+            Resolver::new(&mut interpreter).resolve(&stmts)?;
+            interpreter.interpret(stmts.iter().collect())?;
+            Ok(interpreter)
+        };
+        run().map_err(|error| error.to_string())
+    }
+
+    fn run_in(interpreter: &mut Interpreter, source: &str) {
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let stmts = Parser::new(scanner.tokens).parse().unwrap();
+        Resolver::new(interpreter).resolve(&stmts).unwrap();
+        interpreter.interpret(stmts.iter().collect()).unwrap();
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> LiteralValue {
+        interpreter.globals.borrow().get(name).unwrap()
+    }
+
+    #[test]
+    fn call_function_with_return() {
+        let interpreter = run("fun add(a, b) { return a + b; } var x = add(1, 2);").unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(3));
+    }
+
+    #[test]
+    fn function_without_return_gives_nil() {
+        let interpreter = run("fun noop() { 1 + 1; } var x = noop();").unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Nil);
+    }
+
+    #[test]
+    fn return_unwinds_loops() {
+        let source = "
+            fun first_above(limit) {
+                var i = 0;
+                while (true) {
+                    if (i > limit) return i;
+                    i = i + 1;
+                }
+            }
+            var x = first_above(4);";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(5));
+    }
+
+    #[test]
+    fn recursion() {
+        let source = "
+            fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+            var x = fib(10);";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(55));
+    }
+
+    #[test]
+    fn arity_mismatch() {
+        let result = run("fun f(a) { return a; } f(1, 2);");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn calling_non_callable() {
+        assert!(run("var x = 1; x();").is_err());
+    }
+
+    #[test]
+    fn return_at_top_level() {
+        assert!(run("return 1;").is_err());
+    }
+
+    #[test]
+    fn closure_counter() {
+        let source = "
+            fun make_counter() {
+                var count = 0;
+                fun increment() {
+                    count = count + 1;
+                    return count;
+                }
+                return increment;
+            }
+            var counter = make_counter();
+            counter();
+            counter();
+            var x = counter();
+            var other = make_counter();
+            var y = other();";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(3));
+        assert_eq!(global(&interpreter, "y"), LiteralValue::Int(1));
+    }
+
+    #[test]
+    fn nested_closures_outlive_their_block() {
+        let source = "
+            var adder;
+            {
+                var base = 10;
+                fun outer(a) {
+                    fun inner(b) {
+                        return base + a + b;
+                    }
+                    return inner;
+                }
+                adder = outer(5);
+            }
+            var x = adder(1);";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(16));
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let source = "
+            var get;
+            var set;
+            {
+                var value = 1;
+                fun getter() { return value; }
+                fun setter(v) { value = v; }
+                get = getter;
+                set = setter;
+            }
+            set(42);
+            var x = get();";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(42));
+    }
+
+    #[test]
+    fn class_fields_and_methods() {
+        let source = "
+            class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                }
+                sum() {
+                    return this.x + this.y;
+                }
+            }
+            var p = Point(1, 2);
+            p.x = 10;
+            var x = p.sum();
+            var y = p.y;";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(12));
+        assert_eq!(global(&interpreter, "y"), LiteralValue::Int(2));
+    }
+
+    #[test]
+    fn bound_methods_keep_their_instance() {
+        let source = "
+            class Counter {
+                init() { this.count = 0; }
+                increment() {
+                    this.count = this.count + 1;
+                    return this.count;
+                }
+            }
+            var counter = Counter();
+            var increment = counter.increment;
+            increment();
+            var x = increment();";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(2));
+    }
+
+    #[test]
+    fn initializer_returns_instance() {
+        let source = "
+            class Thing {
+                init() { return; }
+            }
+            var thing = Thing();
+            var again = thing.init();
+            var same = thing == again;";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "same"), LiteralValue::True);
+    }
+
+    #[test]
+    fn class_arity_follows_initializer() {
+        assert!(run("class A { init(a) {} } A();").is_err());
+        assert!(run("class A {} A(1);").is_err());
+    }
+
+    #[test]
+    fn undefined_property() {
+        assert!(run("class A {} var a = A(); a.missing;").is_err());
+        assert!(run("var a = 1; a.field = 2;").is_err());
+    }
+
+    #[test]
+    fn inherited_methods() {
+        let source = "
+            class Animal {
+                init(name) { this.name = name; }
+                describe() { return this.name + \" makes a sound\"; }
+            }
+            class Dog < Animal {}
+            var x = Dog(\"rex\").describe();";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::StringValue("rex makes a sound".to_string()));
+    }
+
+    #[test]
+    fn super_calls_bind_current_instance() {
+        let source = "
+            class A {
+                method() { return this.tag + \"A\"; }
+            }
+            class B < A {
+                method() { return super.method() + \"B\"; }
+            }
+            class C < B {
+                method() { return super.method() + \"C\"; }
+            }
+            var c = C();
+            c.tag = \">\";
+            var x = c.method();";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "x"), LiteralValue::StringValue(">ABC".to_string()));
+    }
+
+    #[test]
+    fn invalid_superclass() {
+        assert!(run("var NotAClass = 1; class A < NotAClass {}").is_err());
+        assert!(run("class A < A {}").is_err());
+    }
+
+    #[test]
+    fn closures_bind_to_the_variable_in_scope_at_declaration() {
+        let source = "
+            var a = \"global\";
+            var first;
+            var second;
+            {
+                fun show() { return a; }
+                first = show();
+                var a = \"block\";
+                second = show();
+            }";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "first"), LiteralValue::StringValue("global".to_string()));
+        assert_eq!(global(&interpreter, "second"), LiteralValue::StringValue("global".to_string()));
+    }
+
+    #[test]
+    fn integers_keep_full_precision() {
+        let interpreter = run("var x = 16777217; var y = x + 1;").unwrap();
+        assert!(matches!(global(&interpreter, "x"), LiteralValue::Int(16777217)));
+        assert!(matches!(global(&interpreter, "y"), LiteralValue::Int(16777218)));
+    }
+
+    #[test]
+    fn mixing_ints_and_floats_promotes_to_float() {
+        let interpreter = run("var x = 1 + 0.5; var y = 3 * 2.0; var z = 6 / 3;").unwrap();
+        assert!(matches!(global(&interpreter, "x"), LiteralValue::Float(x) if x == 1.5));
+        assert!(matches!(global(&interpreter, "y"), LiteralValue::Float(y) if y == 6.0));
+        assert!(matches!(global(&interpreter, "z"), LiteralValue::Int(2)));
+    }
+
+    #[test]
+    fn integer_modulo_rounds_down() {
+        let interpreter = run("var r = -7 % 2; var s = 7 % -2;").unwrap();
+        assert_eq!(global(&interpreter, "r"), LiteralValue::Int(1));
+        assert_eq!(global(&interpreter, "s"), LiteralValue::Int(-1));
+    }
+
+    #[test]
+    fn integer_overflow_promotes_to_bigint() {
+        let interpreter = run("var x = 9223372036854775807 + 1; var y = x - 1;").unwrap();
+        assert!(matches!(global(&interpreter, "x"), LiteralValue::BigInt(_)));
+        assert_eq!(global(&interpreter, "x").to_string(), "9223372036854775808");
+        assert!(matches!(global(&interpreter, "y"), LiteralValue::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn integer_division_is_exact() {
+        let interpreter = run("var x = 1 / 3; var y = x + x + x; var z = 0.1 + 0.2 == 3 / 10;").unwrap();
+        assert!(matches!(global(&interpreter, "x"), LiteralValue::Rational(_)));
+        assert_eq!(global(&interpreter, "x").to_string(), "1/3");
+        assert!(matches!(global(&interpreter, "y"), LiteralValue::Int(1)));
+        assert_eq!(global(&interpreter, "z"), LiteralValue::False);
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error_for_exact_numbers() {
+        assert_eq!(run("var x = 1 / 0;").err().as_deref(), Some("Line 1, column 11: Division by zero"));
+        assert_eq!(run("var x = (1 / 2) % 0;").err().as_deref(), Some("Line 1, column 17: Modulo by zero"));
+        assert!(matches!(run("var x = 1.0 / 0;").map(|i| global(&i, "x")), Ok(LiteralValue::Float(x)) if x.is_infinite()));
+    }
+
+    #[test]
+    fn lists_are_shared_between_references() {
+        let source = "
+            var xs = [1, 2, 3];
+            fun set_last(list, value) { list[-1] = value; }
+            set_last(xs, 10);
+            var last = xs[2];
+            var size = len(xs);";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "last"), LiteralValue::Int(10));
+        assert_eq!(global(&interpreter, "size"), LiteralValue::Int(3));
+        assert_eq!(global(&interpreter, "xs").to_string(), "[1, 2, 10]");
+    }
+
+    #[test]
+    fn list_index_out_of_bounds_is_an_error() {
+        assert_eq!(
+            run("var xs = [1, 2]; var x = xs[2];").err().as_deref(),
+            Some("Line 1, column 30: Index 2 out of bounds for list of length 2")
+        );
+        assert_eq!(
+            run("var xs = [1, 2]; xs[-3] = 0;").err().as_deref(),
+            Some("Line 1, column 23: Index -3 out of bounds for list of length 2")
+        );
+    }
+
+    #[test]
+    fn maps_support_get_set_and_delete() {
+        let source = "
+            var m = {\"a\": 1};
+            m[\"b\"] = 2;
+            m[1] = \"int\";
+            var one = m[1.0];
+            var removed = remove(m, \"a\");
+            var present = has(m, \"a\");";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "one"), LiteralValue::StringValue("int".to_string()));
+        assert_eq!(global(&interpreter, "removed"), LiteralValue::Int(1));
+        assert_eq!(global(&interpreter, "present"), LiteralValue::False);
+        assert_eq!(global(&interpreter, "m").to_string(), "{\"b\": 2, 1: \"int\"}");
+    }
+
+    #[test]
+    fn unhashable_map_keys_are_an_error() {
+        assert_eq!(
+            run("var m = {}; m[[1]] = 2;").err().as_deref(),
+            Some("Line 1, column 18: Map keys must be strings, numbers, booleans or nil, got List")
+        );
+    }
+
+    #[test]
+    fn for_in_binds_a_fresh_variable_each_iteration() {
+        let source = "
+            var fns = [nil, nil, nil];
+            for (i in 0..3) {
+                fun get() { return i; }
+                fns[i] = get;
+            }
+            var total = 0;
+            for (f in fns) total = total * 10 + f();";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "total"), LiteralValue::Int(12));
+    }
+
+    #[test]
+    fn for_in_walks_maps_strings_and_inclusive_ranges() {
+        let source = "
+            var keys = \"\";
+            for (k in {\"x\": 1, \"y\": 2}) keys = keys + k;
+            for (c in \"ab\") keys = keys + c;
+            var sum = 0;
+            for (n in 1..=4) sum = sum + n;";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "keys"), LiteralValue::StringValue("xyab".to_string()));
+        assert_eq!(global(&interpreter, "sum"), LiteralValue::Int(10));
+    }
+
+    #[derive(Debug)]
+    struct Countdown(i64);
+
+    impl crate::iter::LoxIterator for Countdown {
+        fn next(&mut self) -> Result<Option<LiteralValue>, String> {
+            self.0 -= 1;
+            Ok((self.0 >= 0).then_some(LiteralValue::Int(self.0)))
+        }
+    }
+
+    #[test]
+    fn for_in_accepts_host_iterators() {
+        let mut scanner = Scanner::new("var seen = []; for (n in countdown) seen = [n, seen];");
+        scanner.scan_tokens().unwrap();
+        let stmts = Parser::new(scanner.tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let countdown = LiteralValue::Iterator(Rc::new(RefCell::new(Countdown(3))));
+        interpreter.globals.borrow_mut().define("countdown".to_string(), countdown);
+        Resolver::new(&mut interpreter).resolve(&stmts).unwrap();
+        interpreter.interpret(stmts.iter().collect()).unwrap();
+        assert_eq!(global(&interpreter, "seen").to_string(), "[0, [1, [2, []]]]");
+    }
+
+    #[test]
+    fn continue_runs_the_for_increment() {
+        let source = "
+            var sum = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                if (i % 3 != 0) continue;
+                if (i > 6) break;
+                sum = sum + i;
+            }";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "sum"), LiteralValue::Int(9));
+    }
+
+    #[test]
+    fn labeled_break_leaves_the_outer_loop() {
+        let source = "
+            var pairs = 0;
+            outer: for (a in 0..5) {
+                for (b in 0..5) {
+                    if (b > a) continue outer;
+                    if (a + b == 6) break outer;
+                    pairs = pairs + 1;
+                }
+            }";
+        let interpreter = run(source).unwrap();
+        assert_eq!(global(&interpreter, "pairs"), LiteralValue::Int(9));
+    }
+
+    #[test]
+    fn interpolation_displays_values_without_quotes() {
+        let source = r#"
+            var name = "lox";
+            var message = "${name} has ${len([1, 2])} items: ${["a", 1.0]} \${literal}";"#;
+        let interpreter = run(source).unwrap();
+        assert_eq!(
+            global(&interpreter, "message"),
+            LiteralValue::StringValue(r#"lox has 2 items: ["a", 1.0] ${literal}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn release_keeps_only_what_live_functions_need() {
+        let mut interpreter = Interpreter::new();
+        for source in [
+            "{ var a = 1; a = a + 1; }",
+            "if (false) { fun never() { var b = 1; return b; } }",
+            "fun f() { var c = 1; return c; }",
+            "fun f() { var d = 2; return d + d; }",
+        ] {
+            run_in(&mut interpreter, source);
+            interpreter.release();
+        }
+        // Only the two uses of `d` in the current `f` are left
+        assert_eq!(interpreter.locals.len(), 2);
+        run_in(&mut interpreter, "var x = f();");
+        assert_eq!(global(&interpreter, "x"), LiteralValue::Int(4));
+    }
+
+    /// Pieces of Lox that random programs are assembled from, so generated
+    /// input gets past the scanner often enough to exercise the later phases.
+    const FRAGMENTS: &[&str] = &[
+        "var", "fun", "class", "<", "this", "super", "return", "if", "else", "while", "for", "in", "break",
+        "continue", "print", "and", "or", "!", "nil", "true", "false", "x", "y", "f", "A", "0", "1", "2.5",
+        "99999999999999999999", "\"s\"", "\"a${", "}\"", "(", ")", "{", "}", "[", "]", ":", ",", ".", ";",
+        "=", "==", "!=", "+", "-", "*", "/", "%", "..", "..=", "x:",
+    ];
+
+    /// Generated programs can loop forever, e.g. `for ( ; ; ) { }`.
+    fn fuzz(source: &str) {
+        let _ = run_with_limits(source, Limits { max_steps: Some(10_000), ..Limits::default() });
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+            fuzz(&String::from_utf8_lossy(&bytes));
+        }
+
+        #[test]
+        fn arbitrary_token_sequences_never_panic(pieces in prop::collection::vec(prop::sample::select(FRAGMENTS), 0..24)) {
+            fuzz(&pieces.join(" "));
+        }
+    }
+
+    /// Values that contain themselves, which the generators above never build.
+    #[test]
+    fn self_referencing_values_never_panic() {
+        let setups = ["var a = [0]; a[0] = a; var b = [0]; b[0] = b;", "var a = {}; a[0] = a; var b = {}; b[0] = b;"];
+        let uses = [
+            "print a;", "print a == b;", "print a != b;", "print [a] == [b];", "print \"${a}\";",
+            "a < b;", "a + 1;", "-a;", "var m = {}; m[a] = 1;", "var m = {}; m[1];", "print len(a);",
+        ];
+        for setup in setups {
+            for source in uses {
+                fuzz(&format!("{} {}", setup, source));
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::ast::LiteralValue;
+use crate::numeric;
+use num_bigint::BigInt;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// The iteration protocol behind `for (x in ...)`. Built-in collections hand
+/// out one of the iterators below; host code can implement this trait and
+/// return the iterator as a `LiteralValue::Iterator` from a native function.
+pub trait LoxIterator: fmt::Debug {
+    /// Returns the next element, or `None` once the iterator is exhausted.
+    fn next(&mut self) -> Result<Option<LiteralValue>, String>;
+}
+
+/// An integer range written `start..end` or `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoxRange {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl LoxRange {
+    /// The number of integers in the range: an `Int`, or a `BigInt` for
+    /// ranges that span more than `i64::MAX` values.
+    pub fn len(&self) -> LiteralValue {
+        numeric::big(BigInt::from((self.end_exclusive() - self.start as i128).max(0)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        if self.inclusive {
+            self.start > self.end
+        } else {
+            self.start >= self.end
+        }
+    }
+
+    /// The first integer past the range, which may not fit in an `i64`.
+    fn end_exclusive(&self) -> i128 {
+        self.end as i128 + self.inclusive as i128
+    }
+}
+
+impl fmt::Display for LoxRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, operator, self.end)
+    }
+}
+
+#[derive(Debug)]
+struct RangeIter {
+    next: i128,
+    end: i128,
+}
+
+impl LoxIterator for RangeIter {
+    fn next(&mut self) -> Result<Option<LiteralValue>, String> {
+        if self.next >= self.end {
+            return Ok(None);
+        }
+        let value = self.next as i64;
+        self.next += 1;
+        Ok(Some(LiteralValue::Int(value)))
+    }
+}
+
+/// Walks a list by position, so elements appended during the loop are
+/// visited too and nothing stays borrowed while the body runs.
+#[derive(Debug)]
+struct ListIter {
+    list: Rc<RefCell<Vec<LiteralValue>>>,
+    index: usize,
+}
+
+impl LoxIterator for ListIter {
+    fn next(&mut self) -> Result<Option<LiteralValue>, String> {
+        let value = self.list.borrow().get(self.index).cloned();
+        self.index += 1;
+        Ok(value)
+    }
+}
+
+/// Yields values collected up front: map keys in insertion order and the
+/// characters of a string.
+#[derive(Debug)]
+struct SnapshotIter {
+    values: std::vec::IntoIter<LiteralValue>,
+}
+
+impl LoxIterator for SnapshotIter {
+    fn next(&mut self) -> Result<Option<LiteralValue>, String> {
+        Ok(self.values.next())
+    }
+}
+
+impl LiteralValue {
+    /// Starts iterating over `self`. Iterator values are returned as-is, so
+    /// looping over one twice continues where the first loop stopped.
+    pub fn iterate(&self) -> Result<Rc<RefCell<dyn LoxIterator>>, String> {
+        let iterator: Rc<RefCell<dyn LoxIterator>> = match self {
+            LiteralValue::Range(range) => Rc::new(RefCell::new(RangeIter {
+                next: range.start as i128,
+                end: range.end_exclusive(),
+            })),
+            LiteralValue::List(list) => Rc::new(RefCell::new(ListIter { list: list.clone(), index: 0 })),
+            LiteralValue::Map(map) => Rc::new(RefCell::new(SnapshotIter {
+                values: map.borrow().keys().into_iter(),
+            })),
+            LiteralValue::StringValue(s) => Rc::new(RefCell::new(SnapshotIter {
+                values: s.chars()
+                    .map(|ch| LiteralValue::StringValue(ch.to_string()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            })),
+            LiteralValue::Iterator(iterator) => iterator.clone(),
+            other => return Err(format!("{} is not iterable", other.to_type())),
+        };
+        Ok(iterator)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: i64, end: i64, inclusive: bool) -> LoxRange {
+        LoxRange { start, end, inclusive }
+    }
+
+    fn first(range: LoxRange, count: usize) -> Vec<LiteralValue> {
+        let iterator = LiteralValue::Range(range).iterate().unwrap();
+        let mut iterator = iterator.borrow_mut();
+        (0..count).map_while(|_| iterator.next().unwrap()).collect()
+    }
+
+    #[test]
+    fn ranges_at_the_edges_of_int() {
+        assert_eq!(range(0, i64::MAX, true).len().to_string(), "9223372036854775808");
+        assert_eq!(range(-1, i64::MAX, false).len().to_string(), "9223372036854775808");
+        assert_eq!(range(i64::MIN, i64::MAX, true).len().to_string(), "18446744073709551616");
+        assert_eq!(range(0, i64::MAX, false).len(), LiteralValue::Int(i64::MAX));
+        assert!(!range(i64::MIN, i64::MAX, true).is_empty());
+        assert!(range(i64::MAX, i64::MIN, true).is_empty());
+        assert_eq!(range(5, 5, false).len(), LiteralValue::Int(0));
+
+        let int = LiteralValue::Int;
+        assert_eq!(first(range(i64::MIN, i64::MAX, true), 2), [int(i64::MIN), int(i64::MIN + 1)]);
+        assert_eq!(first(range(i64::MAX - 1, i64::MAX, true), 3), [int(i64::MAX - 1), int(i64::MAX)]);
+        assert_eq!(first(range(i64::MAX, i64::MAX, false), 1), []);
+    }
+}
@@ -0,0 +1,40 @@
+//! A Lox interpreter with a tree-walking and a bytecode backend.
+//!
+//! Programs embedding scripts should go through [`Engine`], which runs
+//! source text and reads and writes its globals. The modules below it are
+//! the pipeline the `rprt` binary is built from.
+
+pub mod scanner;
+pub mod ast;
+pub mod parser;
+pub mod interpreter;
+pub mod stmt;
+pub mod environment;
+pub mod callable;
+pub mod class;
+pub mod resolver;
+pub mod chunk;
+pub mod compiler;
+pub mod vm;
+pub mod dump;
+pub mod numeric;
+pub mod natives;
+pub mod convert;
+pub mod userdata;
+pub mod console;
+pub mod limits;
+pub mod map;
+pub mod iter;
+pub mod span;
+pub mod diagnostic;
+pub mod error;
+mod engine;
+
+pub use crate::engine::Engine;
+pub use crate::ast::LiteralValue as Value;
+pub use crate::error::{LoxError, RuntimeError};
+pub use crate::convert::{FromValue, IntoValue};
+pub use crate::natives::NativeFunction;
+pub use crate::userdata::UserData;
+pub use crate::console::Console;
+pub use crate::limits::Limits;
@@ -0,0 +1,72 @@
+use crate::error::RuntimeError;
+use crate::span::Span;
+use std::time::{Duration, Instant};
+
+/// Bounds on how much work a program may do before it is stopped with a
+/// runtime error. A step is a statement on the tree-walker and an
+/// instruction on the VM; depth counts calls in progress.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub max_duration: Option<Duration>,
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { max_steps: None, max_duration: None, max_depth: 1024 }
+    }
+}
+
+/// How many steps checking the clock is put off for, as reading it costs
+/// more than a VM instruction.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// The part of `Limits` a running program has used up.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    started: Instant,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self { limits, steps: 0, started: Instant::now() }
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Counts one step of work. Errors have no span; the caller knows what
+    /// was running.
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        let span = Span::default();
+        self.steps += 1;
+        if let Some(limit) = self.limits.max_steps.filter(|limit| self.steps > *limit) {
+            return Err(RuntimeError::StepLimitExceeded { span, limit });
+        }
+        if let Some(limit) = self.limits.max_duration {
+            if self.steps.is_multiple_of(CLOCK_INTERVAL) && self.started.elapsed() > limit {
+                return Err(RuntimeError::TimeLimitExceeded { span, limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails when `depth` calls in progress are more than allowed.
+    pub fn enter(&self, depth: usize) -> Result<(), RuntimeError> {
+        if depth > self.limits.max_depth {
+            return Err(RuntimeError::StackOverflow { span: Span::default(), limit: self.limits.max_depth });
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f`, first moving to a fresh stretch of stack if little is left.
+/// The recursive passes over the syntax tree go through this so deeply
+/// nested programs can't overflow the native stack.
+pub fn deep<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(64 * 1024, 2 * 1024 * 1024, f)
+}
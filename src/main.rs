@@ -1,43 +1,111 @@
-mod scanner;
-mod ast;
-mod parser;
-mod interpreter;
-mod stmt;
-mod environment;
-
-use crate::scanner::*;
-use crate::parser::*;
-use crate::interpreter::*;
+use rprt::scanner::*;
+use rprt::parser::*;
+use rprt::interpreter::*;
+use rprt::resolver::*;
+use rprt::compiler::Compiler;
+use rprt::vm::Vm;
+use rprt::dump;
+use rprt::error::LoxError;
 
 use std::{env, process::exit, fs, io};
-use std::io::{BufRead, Write};
+use std::io::{BufRead, IsTerminal, Write};
+
+
+/// Which engine executes parsed programs. The tree-walker is the reference
+/// implementation; the bytecode VM is selected with `--vm`.
+pub enum Backend {
+    TreeWalker(Interpreter),
+    Bytecode(Vm),
+}
 
+impl Backend {
+    pub fn new(use_vm: bool) -> Self {
+        if use_vm {
+            Backend::Bytecode(Vm::new())
+        } else {
+            Backend::TreeWalker(Interpreter::new())
+        }
+    }
+}
+
+
+/// Debug output requested on the command line. When any dump is enabled the
+/// program is only scanned and parsed, never executed.
+#[derive(Default)]
+pub struct DumpOptions {
+    pub tokens: bool,
+    pub ast: bool,
+    pub outline: bool,
+}
+
+impl DumpOptions {
+    pub fn any(&self) -> bool {
+        self.tokens || self.ast || self.outline
+    }
+}
 
-pub fn run_file(path: &str) -> Result<(), String>{
-    let mut interpreter = Interpreter::new();
-    match fs::read_to_string(path) {
-        Err(msg) => return Err(msg.to_string()),
-        Ok(contents) => return run(&mut interpreter, &contents),
-    } 
 
+/// Runs the script at `path`. On failure, prints what went wrong and
+/// returns the status the process should exit with.
+pub fn run_file(path: &str, use_vm: bool, dump: &DumpOptions, color: bool) -> Result<(), i32> {
+    let mut backend = Backend::new(use_vm);
+    let contents = fs::read_to_string(path).map_err(|msg| {
+        println!("ERROR: {}", msg);
+        66
+    })?;
+    run(&mut backend, &contents, dump).map_err(|error| {
+        println!("{}", error.render(path, &contents, color));
+        error.exit_code()
+    })
 }
 
 
-pub fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), String> {
+pub fn run(backend: &mut Backend, contents: &str, dump: &DumpOptions) -> Result<(), LoxError> {
     let mut scanner = Scanner::new(contents);
     scanner.scan_tokens()?;
     let tokens = scanner.tokens;
 
+    if dump.tokens {
+        for token in &tokens {
+            println!("{:>4} | {}", token.span.line, token);
+        }
+    }
+
     let mut parser = Parser::new(tokens);
     let stmts = parser.parse()?;
-    interpreter.interpret(stmts.iter().collect())?;
 
-    return Ok(());
+    if dump.ast {
+        for stmt in &stmts {
+            println!("{}", stmt);
+        }
+    }
+    if dump.outline {
+        println!("{}", dump::outline(&stmts));
+    }
+    if dump.any() {
+        return Ok(());
+    }
+
+    match backend {
+        Backend::TreeWalker(interpreter) => {
+            let result = Resolver::new(interpreter).resolve(&stmts).map_err(LoxError::from)
+                .and_then(|()| interpreter.interpret(stmts.iter().collect()).map_err(LoxError::from));
+            // The REPL keeps one interpreter for every line it runs
+            interpreter.release();
+            result?;
+        },
+        Backend::Bytecode(vm) => {
+            let function = Compiler::new().compile(&stmts)?;
+            vm.interpret(function)?;
+        },
+    }
+
+    Ok(())
 }
 
 
-fn run_prompt() -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
+fn run_prompt(use_vm: bool, dump: &DumpOptions, color: bool) -> Result<(), String> {
+    let mut backend = Backend::new(use_vm);
     let mut buffer = String::new();
     loop {
         print!("> ");
@@ -57,29 +125,43 @@ fn run_prompt() -> Result<(), String> {
             Err(_) => return Err("Couldnt read stdin".to_string()),
         }
         println!("ECHO: {}", &buffer[current_length..]);
-        match run(&mut interpreter,&buffer[current_length..]) {
+        let line = &buffer[current_length..];
+        match run(&mut backend, line, dump) {
             Ok(_) => (),
-            Err(msg) => println!("{}", msg)
+            Err(error) => println!("{}", error.render("<repl>", line, color))
         }
     }
 }
 
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let mut take_flag = |flag: &str| match args.iter().position(|arg| arg == flag) {
+        Some(index) => {
+            args.remove(index);
+            true
+        },
+        None => false,
+    };
+    let use_vm = take_flag("--vm");
+    let dump = DumpOptions {
+        tokens: take_flag("--dump-tokens"),
+        ast: take_flag("--dump-ast"),
+        outline: take_flag("--dump-outline"),
+    };
+    // Diagnostics are colored only on a terminal, and never when NO_COLOR is set.
+    let color = !take_flag("--no-color") && io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
 
     if args.len() > 2 {
-        println!("Usage: rprt [script]");
+        println!("Usage: rprt [--vm] [--dump-tokens] [--dump-ast] [--dump-outline] [--no-color] [script]");
         exit(64);
     } else if args.len() == 2 {
-        match run_file(&args[1]) {
+        match run_file(&args[1], use_vm, &dump, color) {
             Ok(_) => exit(0),
-            Err(msg) => {
-                println!("ERROR: {}", msg)
-            }
+            Err(status) => exit(status),
         }
     } else {
-        match run_prompt() {
+        match run_prompt(use_vm, &dump, color) {
             Ok(_) => exit(0),
             Err(msg) => {
                 println!("ERROR: {}", msg)
@@ -0,0 +1,173 @@
+use crate::ast::{Compared, LiteralValue, Seen};
+use crate::limits;
+use indexmap::IndexMap;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use std::fmt;
+
+/// The hashable form of a map key. Keys that compare equal with `==` map to
+/// the same `MapKey`: every finite number is reduced to its exact rational
+/// value, so `1`, `1.0` and `2 / 2` are one key and `0.5` is the same key
+/// as `1 / 2`. NaN is rejected because it is not equal to itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(BigRational),
+    Infinity(bool),
+    String(String),
+}
+
+impl MapKey {
+    pub fn from_value(value: &LiteralValue) -> Result<MapKey, String> {
+        let number = match value {
+            LiteralValue::Nil => return Ok(MapKey::Nil),
+            LiteralValue::True => return Ok(MapKey::Bool(true)),
+            LiteralValue::False => return Ok(MapKey::Bool(false)),
+            LiteralValue::StringValue(s) => return Ok(MapKey::String(s.clone())),
+            LiteralValue::Int(x) => return Ok(MapKey::Int(*x)),
+            LiteralValue::BigInt(x) => BigRational::from_integer((**x).clone()),
+            LiteralValue::Rational(x) => (**x).clone(),
+            LiteralValue::Float(x) if x.is_nan() => return Err("NaN can't be used as a map key".to_string()),
+            LiteralValue::Float(x) if x.is_infinite() => return Ok(MapKey::Infinity(*x > 0.0)),
+            LiteralValue::Float(x) => BigRational::from_float(*x).expect("finite float"),
+            other => return Err(format!(
+                "Map keys must be strings, numbers, booleans or nil, got {}", other.to_type()
+            )),
+        };
+        match number.is_integer().then(|| number.to_integer().to_i64()).flatten() {
+            Some(x) => Ok(MapKey::Int(x)),
+            None => Ok(MapKey::Number(number)),
+        }
+    }
+}
+
+/// A dictionary that remembers insertion order. Each entry keeps the key as
+/// it was first written so iteration hands back the original value.
+#[derive(Debug, Default, Clone)]
+pub struct LoxMap {
+    entries: IndexMap<MapKey, (LiteralValue, LiteralValue)>,
+}
+
+impl LoxMap {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &LiteralValue) -> Result<LiteralValue, String> {
+        match self.entries.get(&MapKey::from_value(key)?) {
+            Some((_, value)) => Ok(value.clone()),
+            None => Err(format!("Key {} not found in map", key)),
+        }
+    }
+
+    pub fn insert(&mut self, key: LiteralValue, value: LiteralValue) -> Result<(), String> {
+        let hashed = MapKey::from_value(&key)?;
+        match self.entries.get_mut(&hashed) {
+            Some(entry) => entry.1 = value,
+            None => {
+                self.entries.insert(hashed, (key, value));
+            },
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, key: &LiteralValue) -> Result<bool, String> {
+        Ok(self.entries.contains_key(&MapKey::from_value(key)?))
+    }
+
+    /// Removes `key`, keeping the order of the remaining entries.
+    pub fn remove(&mut self, key: &LiteralValue) -> Result<Option<LiteralValue>, String> {
+        Ok(self.entries.shift_remove(&MapKey::from_value(key)?).map(|(_, value)| value))
+    }
+
+    pub fn keys(&self) -> Vec<LiteralValue> {
+        self.entries.values().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn values(&self) -> Vec<LiteralValue> {
+        self.entries.values().map(|(_, value)| value.clone()).collect()
+    }
+}
+
+impl LoxMap {
+    /// Maps are equal when they hold equal values under the same keys,
+    /// regardless of insertion order. See `LiteralValue::equals`.
+    pub(crate) fn equals(&self, other: &Self, seen: &mut Compared) -> bool {
+        self.len() == other.len() && self.entries.iter().all(|(hashed, (_, value))| {
+            other.entries.get(hashed).is_some_and(|(_, other_value)| value.equals(other_value, seen))
+        })
+    }
+
+    pub(crate) fn write(&self, f: &mut fmt::Formatter, seen: &mut Seen) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.entries.values().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            key.write(f, seen)?;
+            write!(f, ": ")?;
+            limits::deep(|| value.write(f, seen))?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl PartialEq for LoxMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut vec![])
+    }
+}
+
+impl fmt::Display for LoxMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &mut vec![])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_numbers_are_the_same_key() {
+        let half = crate::numeric::rational(BigRational::new(1.into(), 2.into()));
+        assert_eq!(MapKey::from_value(&LiteralValue::Int(1)), MapKey::from_value(&LiteralValue::Float(1.0)));
+        assert_eq!(MapKey::from_value(&half), MapKey::from_value(&LiteralValue::Float(0.5)));
+        assert_ne!(MapKey::from_value(&LiteralValue::Int(1)), MapKey::from_value(&LiteralValue::True));
+    }
+
+    #[test]
+    fn unhashable_keys_are_rejected() {
+        assert!(MapKey::from_value(&LiteralValue::Float(f64::NAN)).is_err());
+        assert!(MapKey::from_value(&LiteralValue::list(vec![])).is_err());
+    }
+
+    #[test]
+    fn entries_keep_insertion_order() {
+        let mut map = LoxMap::default();
+        for key in ["b", "a", "c"] {
+            map.insert(LiteralValue::StringValue(key.to_string()), LiteralValue::Nil).unwrap();
+        }
+        map.remove(&LiteralValue::StringValue("a".to_string())).unwrap();
+        map.insert(LiteralValue::StringValue("a".to_string()), LiteralValue::Nil).unwrap();
+        assert_eq!(LiteralValue::list(map.keys()).to_string(), r#"["b", "c", "a"]"#);
+    }
+
+    #[test]
+    fn map_holding_itself_prints_and_compares() {
+        let cyclic = || {
+            let map = LiteralValue::Map(Default::default());
+            map.set_index(&LiteralValue::StringValue("self".to_string()), map.clone()).unwrap();
+            map
+        };
+        assert_eq!(cyclic().to_string(), r#"{"self": {...}}"#);
+        assert!(cyclic() == cyclic());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::ast::LiteralValue;
+use crate::callable::Callable;
+use crate::console::Console;
+use crate::convert::{FromValue, IntoResult, IntoValue};
+use crate::error::RuntimeError;
+use crate::map::LoxMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type NativeFn = dyn Fn(&[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+
+/// A function implemented in Rust and exposed to scripts as a global.
+/// `arity` is `None` for functions that take any number of arguments;
+/// otherwise callers check it before `function` runs.
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Option<usize>,
+    pub function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: Option<usize>,
+        function: impl Fn(&[LiteralValue]) -> Result<LiteralValue, RuntimeError> + 'static,
+    ) -> Self {
+        Self { name: name.to_string(), arity, function: Box::new(function) }
+    }
+
+    /// Wraps a Rust function whose parameters and result convert to and
+    /// from script values, like `|x: f64, y: f64| x.max(y)`.
+    pub fn typed<Args, F: HostFunction<Args> + 'static>(name: &str, function: F) -> Self {
+        let owner = name.to_string();
+        Self::new(name, Some(F::ARITY), move |arguments| function.call(&owner, arguments))
+    }
+
+    pub fn into_value(self) -> LiteralValue {
+        LiteralValue::Callable(Callable::Native(Rc::new(self)))
+    }
+}
+
+/// Rust closures taking up to four `FromValue` arguments and returning an
+/// `IntoResult`. `Args` is the tuple of argument types; it only serves to
+/// tell the implementations apart.
+pub trait HostFunction<Args> {
+    const ARITY: usize;
+
+    fn call(&self, name: &str, arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+}
+
+macro_rules! impl_host_function {
+    ($arity:expr $(, $arg:ident $index:tt)*) => {
+        impl<F, R, $($arg: FromValue),*> HostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R,
+            R: IntoResult,
+        {
+            const ARITY: usize = $arity;
+
+            #[allow(unused_variables)]
+            fn call(&self, name: &str, arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+                self($(argument::<$arg>(name, arguments, $index)?),*).into_result()
+            }
+        }
+    };
+}
+
+impl_host_function!(0);
+impl_host_function!(1, A 0);
+impl_host_function!(2, A 0, B 1);
+impl_host_function!(3, A 0, B 1, C 2);
+impl_host_function!(4, A 0, B 1, C 2, D 3);
+
+fn argument<T: FromValue>(name: &str, arguments: &[LiteralValue], index: usize) -> Result<T, RuntimeError> {
+    T::from_value(&arguments[index])
+        .map_err(|error| format!("Argument {} of '{}': {}", index + 1, name, error.message()).into())
+}
+
+/// The natives every backend defines in its global scope. `input()` reads
+/// from `console`.
+pub fn globals(console: &Console) -> Vec<(String, LiteralValue)> {
+    let console = console.clone();
+    let natives = [
+        NativeFunction::new("input", Some(0), move |_| Ok(console.read_line()?.into_value())),
+        NativeFunction::new("len", Some(1), len),
+        NativeFunction::new("has", Some(2), has),
+        NativeFunction::new("remove", Some(2), remove),
+        NativeFunction::new("keys", Some(1), keys),
+        NativeFunction::new("values", Some(1), values),
+    ];
+    natives.into_iter()
+        .map(|native| (native.name.clone(), native.into_value()))
+        .collect()
+}
+
+fn len(arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    let len = match &arguments[0] {
+        LiteralValue::List(elements) => elements.borrow().len(),
+        LiteralValue::Map(map) => map.borrow().len(),
+        LiteralValue::Range(range) => return Ok(range.len()),
+        LiteralValue::StringValue(s) => s.chars().count(),
+        other => return Err(format!("len() expects a List, Map, Range or String, got {}", other.to_type()).into()),
+    };
+    Ok(LiteralValue::Int(len as i64))
+}
+
+fn expect_map<'a>(function: &str, value: &'a LiteralValue) -> Result<&'a Rc<RefCell<LoxMap>>, String> {
+    match value {
+        LiteralValue::Map(map) => Ok(map),
+        other => Err(format!("{}() expects a Map, got {}", function, other.to_type())),
+    }
+}
+
+fn has(arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    let map = expect_map("has", &arguments[0])?;
+    let found = map.borrow().contains(&arguments[1])?;
+    Ok(LiteralValue::from_bool(found))
+}
+
+/// Deletes a key and returns its value, or nil when it wasn't there.
+fn remove(arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    let map = expect_map("remove", &arguments[0])?;
+    let removed = map.borrow_mut().remove(&arguments[1])?;
+    Ok(removed.unwrap_or(LiteralValue::Nil))
+}
+
+fn keys(arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    let map = expect_map("keys", &arguments[0])?;
+    let keys = map.borrow().keys();
+    Ok(LiteralValue::list(keys))
+}
+
+fn values(arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+    let map = expect_map("values", &arguments[0])?;
+    let values = map.borrow().values();
+    Ok(LiteralValue::list(values))
+}
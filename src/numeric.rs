@@ -0,0 +1,246 @@
+use crate::ast::LiteralValue;
+use crate::scanner::TokenType;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// Position of a number in the tower `Int < BigInt < Rational < Float`.
+/// A binary operator lifts both operands to the higher of their two levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Int,
+    Big,
+    Rational,
+    Float,
+}
+
+fn level(value: &LiteralValue) -> Option<Level> {
+    match value {
+        LiteralValue::Int(_) => Some(Level::Int),
+        LiteralValue::BigInt(_) => Some(Level::Big),
+        LiteralValue::Rational(_) => Some(Level::Rational),
+        LiteralValue::Float(_) => Some(Level::Float),
+        _ => None,
+    }
+}
+
+/// Wraps a big integer, demoting it to `Int` when it fits in 64 bits so
+/// every integer has exactly one representation.
+pub fn big(value: BigInt) -> LiteralValue {
+    match value.to_i64() {
+        Some(x) => LiteralValue::Int(x),
+        None => LiteralValue::BigInt(Rc::new(value)),
+    }
+}
+
+/// Wraps a rational, demoting it to an integer when the denominator is 1.
+pub fn rational(value: BigRational) -> LiteralValue {
+    if value.is_integer() {
+        big(value.to_integer())
+    } else {
+        LiteralValue::Rational(Rc::new(value))
+    }
+}
+
+fn to_big(value: &LiteralValue) -> BigInt {
+    match value {
+        LiteralValue::Int(x) => BigInt::from(*x),
+        LiteralValue::BigInt(x) => (**x).clone(),
+        _ => unreachable!("{} is not an integer", value.to_type()),
+    }
+}
+
+fn to_rational(value: &LiteralValue) -> BigRational {
+    match value {
+        LiteralValue::Rational(x) => (**x).clone(),
+        integer => BigRational::from_integer(to_big(integer)),
+    }
+}
+
+pub(crate) fn to_f64(value: &LiteralValue) -> f64 {
+    match value {
+        LiteralValue::Int(x) => *x as f64,
+        LiteralValue::BigInt(x) => x.to_f64().unwrap_or(f64::NAN),
+        LiteralValue::Rational(x) => x.to_f64().unwrap_or(f64::NAN),
+        LiteralValue::Float(x) => *x,
+        _ => unreachable!("{} is not a number", value.to_type()),
+    }
+}
+
+pub fn is_zero(value: &LiteralValue) -> bool {
+    match value {
+        LiteralValue::Int(x) => *x == 0,
+        LiteralValue::BigInt(x) => x.is_zero(),
+        LiteralValue::Rational(x) => x.is_zero(),
+        LiteralValue::Float(x) => *x == 0.0,
+        _ => false,
+    }
+}
+
+/// Orders two numbers of any level. Comparisons are exact even between a
+/// float and an exact number, so `0.1 != 1 / 10`; NaN is unordered.
+pub fn compare(left: &LiteralValue, right: &LiteralValue) -> Option<Ordering> {
+    // Loop conditions compare `Int`s all the time; skip the conversions
+    match (left, right) {
+        (LiteralValue::Int(x), LiteralValue::Int(y)) => return Some(x.cmp(y)),
+        // denominators are always positive, so this is `x < y` scaled by it
+        (LiteralValue::Rational(x), LiteralValue::Int(y)) => return Some(x.numer().cmp(&(x.denom() * y))),
+        (LiteralValue::Int(x), LiteralValue::Rational(y)) => return Some((y.denom() * x).cmp(y.numer())),
+        _ => (),
+    }
+    match level(left)?.max(level(right)?) {
+        Level::Int | Level::Big => Some(to_big(left).cmp(&to_big(right))),
+        Level::Rational => Some(to_rational(left).cmp(&to_rational(right))),
+        Level::Float => match (left, right) {
+            (LiteralValue::Float(x), LiteralValue::Float(y)) => x.partial_cmp(y),
+            (LiteralValue::Float(x), exact) => compare_float_to_exact(*x, exact),
+            (exact, LiteralValue::Float(y)) => compare_float_to_exact(*y, exact).map(Ordering::reverse),
+            _ => None,
+        },
+    }
+}
+
+fn compare_float_to_exact(x: f64, exact: &LiteralValue) -> Option<Ordering> {
+    if x.is_infinite() {
+        return Some(if x > 0.0 { Ordering::Greater } else { Ordering::Less });
+    }
+    // every finite float is exactly some fraction with a power of two below
+    let x = BigRational::from_float(x)?;
+    Some(x.cmp(&to_rational(exact)))
+}
+
+pub fn negate(value: &LiteralValue) -> Option<LiteralValue> {
+    match value {
+        LiteralValue::Int(x) => Some(match x.checked_neg() {
+            Some(negated) => LiteralValue::Int(negated),
+            None => big(-BigInt::from(*x)),
+        }),
+        LiteralValue::BigInt(x) => Some(big(-(**x).clone())),
+        LiteralValue::Rational(x) => Some(rational(-(**x).clone())),
+        LiteralValue::Float(x) => Some(LiteralValue::Float(-x)),
+        _ => None,
+    }
+}
+
+/// Arithmetic and comparison on two numbers.
+///
+/// Integers never overflow: results that leave the `i64` range become
+/// `BigInt`s. `/` on exact numbers is exact, giving a `Rational` when the
+/// division doesn't come out even, and `%` rounds towards negative infinity
+/// so the result takes the sign of the divisor. Floats follow IEEE rules,
+/// so `1.0 / 0` is `inf` while `1 / 0` is an error.
+pub fn binary(left: &LiteralValue, operator: TokenType, right: &LiteralValue) -> Result<LiteralValue, String> {
+    let level = match (level(left), level(right)) {
+        (Some(x), Some(y)) => x.max(y),
+        _ => return Err(format!("{:?} is not implemented for operands {} and {}", operator, left.to_type(), right.to_type())),
+    };
+
+    let ordering = || compare(left, right);
+    match operator {
+        TokenType::Less => return Ok(LiteralValue::from_bool(ordering() == Some(Ordering::Less))),
+        TokenType::LessEqual => return Ok(LiteralValue::from_bool(matches!(ordering(), Some(Ordering::Less | Ordering::Equal)))),
+        TokenType::Greater => return Ok(LiteralValue::from_bool(ordering() == Some(Ordering::Greater))),
+        TokenType::GreaterEqual => return Ok(LiteralValue::from_bool(matches!(ordering(), Some(Ordering::Greater | Ordering::Equal)))),
+        TokenType::EqualEqual => return Ok(LiteralValue::from_bool(ordering() == Some(Ordering::Equal))),
+        TokenType::BangEqual => return Ok(LiteralValue::from_bool(ordering() != Some(Ordering::Equal))),
+        _ => (),
+    }
+
+    match (level, left, right) {
+        (Level::Int, LiteralValue::Int(x), LiteralValue::Int(y)) => match int_op(*x, operator, *y)? {
+            Some(value) => Ok(value),
+            // overflowed or didn't divide evenly, redo it with unbounded precision
+            None if operator == TokenType::Slash => rational_op(to_rational(left), operator, to_rational(right)),
+            None => big_op(to_big(left), operator, to_big(right)),
+        },
+        (Level::Int | Level::Big, _, _) if operator == TokenType::Slash => {
+            rational_op(to_rational(left), operator, to_rational(right))
+        },
+        (Level::Int | Level::Big, _, _) => big_op(to_big(left), operator, to_big(right)),
+        (Level::Rational, _, _) => rational_op(to_rational(left), operator, to_rational(right)),
+        (Level::Float, _, _) => float_op(to_f64(left), operator, to_f64(right)),
+    }
+}
+
+/// The fast path for two small integers. `Ok(None)` means the result
+/// doesn't fit in an `Int` and has to be computed at a higher level.
+fn int_op(x: i64, operator: TokenType, y: i64) -> Result<Option<LiteralValue>, String> {
+    let value = match operator {
+        TokenType::Plus => x.checked_add(y),
+        TokenType::Minus => x.checked_sub(y),
+        TokenType::Star => x.checked_mul(y),
+        TokenType::Slash => {
+            if y == 0 {
+                return Err("Division by zero".to_string());
+            }
+            match x.checked_rem(y) {
+                Some(0) => x.checked_div(y),
+                _ => None,
+            }
+        },
+        TokenType::Percent => {
+            if y == 0 {
+                return Err("Modulo by zero".to_string());
+            }
+            let remainder = x.wrapping_rem(y);
+            Some(if remainder != 0 && (remainder < 0) != (y < 0) { remainder + y } else { remainder })
+        },
+        _ => return Err(format!("{:?} is not implemented for operands Int and Int", operator)),
+    };
+    Ok(value.map(LiteralValue::Int))
+}
+
+fn big_op(x: BigInt, operator: TokenType, y: BigInt) -> Result<LiteralValue, String> {
+    let value = match operator {
+        TokenType::Plus => x + y,
+        TokenType::Minus => x - y,
+        TokenType::Star => x * y,
+        TokenType::Percent => {
+            if y.is_zero() {
+                return Err("Modulo by zero".to_string());
+            }
+            x.mod_floor(&y)
+        },
+        _ => return Err(format!("{:?} is not implemented for operands BigInt and BigInt", operator)),
+    };
+    Ok(big(value))
+}
+
+fn rational_op(x: BigRational, operator: TokenType, y: BigRational) -> Result<LiteralValue, String> {
+    let value = match operator {
+        TokenType::Plus => x + y,
+        TokenType::Minus => x - y,
+        TokenType::Star => x * y,
+        TokenType::Slash => {
+            if y.is_zero() {
+                return Err("Division by zero".to_string());
+            }
+            x / y
+        },
+        TokenType::Percent => {
+            if y.is_zero() {
+                return Err("Modulo by zero".to_string());
+            }
+            let quotient = (&x / &y).floor();
+            x - y * quotient
+        },
+        _ => return Err(format!("{:?} is not implemented for operands Rational and Rational", operator)),
+    };
+    Ok(rational(value))
+}
+
+fn float_op(x: f64, operator: TokenType, y: f64) -> Result<LiteralValue, String> {
+    let value = match operator {
+        TokenType::Plus => x + y,
+        TokenType::Minus => x - y,
+        TokenType::Star => x * y,
+        TokenType::Slash => x / y,
+        // floored like the exact version so both agree on the sign
+        TokenType::Percent => x - y * (x / y).floor(),
+        _ => return Err(format!("{:?} is not implemented for operands Float and Float", operator)),
+    };
+    Ok(LiteralValue::Float(value))
+}
@@ -1,25 +1,49 @@
 use crate::scanner::{Token, TokenType};
 use crate::ast::{Expr, LiteralValue};
+use crate::error::ParseError;
+use crate::limits;
+use crate::span::Span;
 use crate::stmt::Stmt;
+use std::rc::Rc;
+
+/// How deeply brackets, blocks, statement bodies and prefix operators may
+/// nest. Freeing nested statements recurses once per level, so this bounds
+/// its stack use. Chains like `1 + 1 + ... + 1` don't count as nesting.
+const MAX_NESTING: usize = 1000;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    depth: usize,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        if tokens.last().map(|token| token.token_type) != Some(TokenType::Eof) {
+            let span = match tokens.last() {
+                Some(token) => token.span.after(),
+                None => Span { line: 1, column: 1, end_line: 1, end_column: 1, ..Span::default() },
+            };
+            tokens.push(Token {
+                token_type: TokenType::Eof,
+                lexeme: "".to_string(),
+                literal: None,
+                span,
+            });
+        }
         Self {
-            tokens: tokens,
+            tokens,
             current: 0,
+            depth: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut stmts = vec![];
         let mut errors = vec![];
 
         while !self.is_at_end() {
+            self.depth = 0;
             let stmt = self.declaration();
             match stmt {
                 Ok(s) => stmts.push(s),
@@ -29,15 +53,19 @@ impl Parser {
                 }
             }
         }
-        if errors.len() == 0 {
+        if errors.is_empty() {
             Ok(stmts)
         } else {
-            Err(errors.join("\n"))
+            Err(errors)
         }
     }
 
-    fn declaration(&mut self) -> Result<Stmt, String> {
-        if self.match_token(TokenType::Var) {
+    fn declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token(TokenType::Class) {
+            self.class_declaration()
+        } else if self.match_token(TokenType::Fun) {
+            self.function("function")
+        } else if self.match_token(TokenType::Var) {
             match self.var_declaration() {
                 Ok(stmt) => Ok(stmt),
                 Err(msg) => {
@@ -50,22 +78,71 @@ impl Parser {
         }
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, String> {
-        let token = self.consume(TokenType::Identifier, "Expected variable name")?;
-        let initializer;
-        if self.match_token(TokenType::Equal) {
-            initializer = self.expression()?;
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, "Expected class name")?;
+
+        let superclass = if self.match_token(TokenType::Less) {
+            let superclass_name = self.consume(TokenType::Identifier, "Expected superclass name")?;
+            if superclass_name.lexeme == name.lexeme {
+                return Err(ParseError::InheritsFromItself { span: superclass_name.span, class: name.span });
+            }
+            Some(Expr::Variable { id: Expr::next_id(), name: superclass_name })
         } else {
-            initializer = Expr::Literal { value: LiteralValue::Nil };
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "Expected '{' before class body")?;
+
+        let mut methods = vec![];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
         }
+        self.consume(TokenType::RightBrace, "Expected '}' after class body")?;
+        Ok(Stmt::Class { name, superclass, methods })
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, &format!("Expected {} name", kind))?;
+        self.consume(TokenType::LeftParen, &format!("Expected '(' after {} name", kind))?;
+
+        let mut params = vec![];
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    return Err(ParseError::TooMany { span: self.peek().span, limit: 255, what: "parameters" });
+                }
+                params.push(self.consume(TokenType::Identifier, "Expected parameter name")?);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expected ')' after parameters")?;
+
+        self.consume(TokenType::LeftBrace, &format!("Expected '{{' before {} body", kind))?;
+        let body = match self.block_statement()? {
+            Stmt::Block { span: _, statements } => statements,
+            _ => unreachable!("block_statement always returns a block"),
+        };
+        Ok(Stmt::Function { name, params, body: Rc::new(body) })
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let token = self.consume(TokenType::Identifier, "Expected variable name")?;
+        let initializer = if self.match_token(TokenType::Equal) {
+            self.expression()?
+        } else {
+            Expr::Literal { span: token.span, value: LiteralValue::Nil }
+        };
         self.consume(TokenType::Semicolon, "Expected ';' after variable declaration")?;
-        Ok(Stmt::Var { name: token, initializer: initializer })
+        Ok(Stmt::Var { name: token, initializer })
     }
 
-    fn statement(&mut self) -> Result<Stmt, String> {
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
         if self.match_token(TokenType::Print) {
             self.print_statement()
-        } else if self.match_token(TokenType::LeftBrace) {
+        } else if self.check(TokenType::LeftBrace) && !self.starts_map_literal() {
+            self.advance();
             self.block_statement()
         } else if self.match_token(TokenType::If) {
             self.if_statement()
@@ -73,147 +150,234 @@ impl Parser {
             self.while_statement()
         } else if self.match_token(TokenType::For) {
             self.for_statement()  
+        } else if self.match_token(TokenType::Return) {
+            self.return_statement()
+        } else if self.match_token(TokenType::Break) {
+            let keyword = self.previous();
+            let label = self.loop_label()?;
+            Ok(Stmt::Break { keyword, label })
+        } else if self.match_token(TokenType::Continue) {
+            let keyword = self.previous();
+            let label = self.loop_label()?;
+            Ok(Stmt::Continue { keyword, label })
+        } else if self.check(TokenType::Identifier) && self.next_is(TokenType::Colon) {
+            self.labeled_statement()
         } else {
             self.expression_statement()
         }
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, String> {
+    /// A `{` at the start of a statement opens a block unless it is followed
+    /// by a single token and a `:`. The one block that starts that way is one
+    /// whose first statement is a labeled loop, told apart by the `while` or
+    /// `for` after the colon, which no map value can start with. `{}` on its
+    /// own is an empty block; wrap map literals in parentheses to use them as
+    /// statements with more complex first keys.
+    fn starts_map_literal(&self) -> bool {
+        let token_type = |offset: usize| self.tokens.get(self.current + offset).map(|token| token.token_type);
+        token_type(2) == Some(TokenType::Colon)
+            && !matches!(token_type(3), Some(TokenType::While | TokenType::For))
+    }
+
+    fn next_is(&self, token_type: TokenType) -> bool {
+        self.tokens.get(self.current + 1).is_some_and(|token| token.token_type == token_type)
+    }
+
+    fn labeled_statement(&mut self) -> Result<Stmt, ParseError> {
+        let label = self.advance();
+        self.advance();
+        let body = if self.match_token(TokenType::While) {
+            self.while_statement()?
+        } else if self.match_token(TokenType::For) {
+            self.for_statement()?
+        } else {
+            return Err(ParseError::LabelOnNonLoop { span: label.span, statement: self.peek().span });
+        };
+        Ok(Stmt::Labeled { label, body: Box::new(body) })
+    }
+
+    /// The optional label after `break`/`continue`, plus the closing `;`.
+    fn loop_label(&mut self) -> Result<Option<Token>, ParseError> {
+        let label = if self.check(TokenType::Identifier) {
+            Some(self.advance())
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expected ';' after loop control statement")?;
+        Ok(label)
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        let value = if !self.check(TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expected ';' after return value")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expected '(' after 'for'.")?;
 
-        let initializer;
-        if self.match_token(TokenType::Semicolon) {
-            initializer = None;
+        if self.starts_for_in() {
+            return self.for_in_statement();
+        }
+
+        let (var_decl, expr_stmt) = if self.match_token(TokenType::Semicolon) {
+            (None, None)
         } else if self.match_token(TokenType::Var) {
-            let var_decl = self.var_declaration()?;
-            initializer = Some(var_decl);
+            (Some(Box::new(self.var_declaration()?)), None)
         } else {
-            let expr = self.expression_statement()?;
-            initializer = Some(expr);
-        }
+            (None, Some(Box::new(self.expression_statement()?)))
+        };
 
-        let condition;
-        if !self.check(TokenType::Semicolon) {
+        let condition = if !self.check(TokenType::Semicolon) {
             let expr = self.expression()?;
-            condition = Some(expr);
+            Some(expr)
         } else {
-            condition = None;
-        }
+            None
+        };
 
         self.consume(TokenType::Semicolon, "Expected ';' after loop condition.")?;
 
-        let increment;
-        if !self.check(TokenType::RightParen) {
+        let increment = if !self.check(TokenType::RightParen) {
             let expr = self.expression()?;
-            increment = Some(expr);
+            Some(expr)
         } else {
-            increment = None;
-        }
+            None
+        };
         self.consume(TokenType::RightParen, "Expected ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
+        let body = Box::new(self.nested(Self::statement)?);
 
-        if let Some(incr) = increment {
-            body = Stmt::Block { statements: vec![Box::new(body), Box::new(Stmt::Expression { expression: incr })] };
-        }
+        Ok(Stmt::ForStmt { var_decl, expr_stmt, condition, increment, body })
+    }
 
-        let cond;
-        match condition {
-            None => cond = Expr::Literal { value: LiteralValue::True },
-            Some(c) => cond = c,
-        }
-        body = Stmt::WhileStmt { condition: cond, body: Box::new(body) };
+    /// `for (x in ...)` and `for (var x in ...)`.
+    fn starts_for_in(&self) -> bool {
+        let offset = match self.tokens.get(self.current) {
+            Some(token) if token.token_type == TokenType::Var => 1,
+            _ => 0,
+        };
+        let is = |index: usize, token_type: TokenType| {
+            self.tokens.get(index).is_some_and(|token| token.token_type == token_type)
+        };
+        is(self.current + offset, TokenType::Identifier) && is(self.current + offset + 1, TokenType::In)
+    }
 
-        if let Some(init) = initializer {
-            body = Stmt::Block { statements: vec![Box::new(init), Box::new(body)] };
-        }
+    fn for_in_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.match_token(TokenType::Var);
+        let variable = self.consume(TokenType::Identifier, "Expected loop variable name.")?;
+        self.consume(TokenType::In, "Expected 'in' after loop variable.")?;
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after for clauses.")?;
+        let body = Box::new(self.nested(Self::statement)?);
 
-        Ok(body)
+        Ok(Stmt::ForInStmt { variable, iterable, body })
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, String> {
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expected '(' after 'while'")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expected ')' after condition")?;
-        let body = self.statement()?;
+        let body = self.nested(Self::statement)?;
         Ok(Stmt::WhileStmt {
-            condition: condition,
+            condition,
             body: Box::new(body) 
         })
     }
 
-    fn if_statement(&mut self) -> Result<Stmt, String> {
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expected '(' after 'if'")?;
         let predicate = self.expression()?;
         self.consume(TokenType::RightParen, "Expected ')' after if-predicate")?;
 
-        let then = Box::new(self.statement()?);
+        let then = Box::new(self.nested(Self::statement)?);
         let els = if self.match_token(TokenType::Else) {
-            let stmt = self.statement()?;
+            let stmt = self.nested(Self::statement)?;
             Some(Box::new(stmt))
         } else {
             None
         };
-        Ok(Stmt::IfStmt { predicate: predicate, then: then, els: els })
+        Ok(Stmt::IfStmt { predicate, then, els })
     }
 
-    fn block_statement(&mut self) -> Result<Stmt, String> {
+    /// Parses the rest of a block whose `{` has just been consumed.
+    fn block_statement(&mut self) -> Result<Stmt, ParseError> {
+        let brace = self.previous();
         let mut statements = vec![];
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            let decl = self.declaration()?;
-            statements.push(Box::new(decl));
+            let decl = self.nested(Self::declaration)?;
+            statements.push(decl);
         }
-        self.consume(TokenType::RightBrace, "Expected '}' after block.")?;
-        Ok(Stmt::Block { statements })
+        let end = self.consume(TokenType::RightBrace, "Expected '}' after block.")?;
+        Ok(Stmt::Block { span: brace.span.to(end.span), statements })
     }
 
-    fn print_statement(&mut self) -> Result<Stmt, String> {
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expected ';' after value.")?;
-        Ok(Stmt::Print { expression: value })
+        Ok(Stmt::Print { keyword, expression: value })
     }
 
-    fn expression_statement(&mut self) -> Result<Stmt, String> {
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expected ';' after expression.")?;
         Ok(Stmt::Expression { expression: expr })
     }
 
-    pub fn expression(&mut self) -> Result<Expr, String> {
-        self.assignment()
+    pub fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.nested(Self::assignment)
     }
 
-    fn assignment(&mut self) -> Result<Expr, String> {
-        let expr = self.or()?;
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.or()?;
 
         if self.match_token(TokenType::Equal) {
-            let value = self.assignment()?;
-
-            match expr {
-                Expr::Variable { name } => Ok(Expr::Assign {
-                    name: name,
+            let target = expr.span();
+            let equals = self.previous();
+            let value = self.nested(Self::assignment)?;
+
+            match &mut expr {
+                Expr::Variable { id, name } => Ok(Expr::Assign {
+                    id: *id,
+                    name: name.clone(),
+                    value: Box::from(value),
+                }),
+                Expr::Get { object, name } => Ok(Expr::Set {
+                    object: Box::new(Expr::take(object)),
+                    name: name.clone(),
                     value: Box::from(value),
                 }),
-                _ => Err("Invalid assignment target.".to_string())
+                Expr::Index { object, bracket, index } => Ok(Expr::IndexSet {
+                    object: Box::new(Expr::take(object)),
+                    bracket: bracket.clone(),
+                    index: Box::new(Expr::take(index)),
+                    value: Box::from(value),
+                }),
+                _ => Err(ParseError::InvalidAssignmentTarget { span: equals.span, target }),
             }
         } else {
             Ok(expr)
         }
     }
 
-    fn or(&mut self) -> Result<Expr, String> {
+    fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
 
         while self.match_token(TokenType::Or) {
             let operator = self.previous();
             let right = self.and()?;
-            expr = Expr::Logical { left: Box::new(expr), operator: operator, right: Box::new(right) };
+            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) };
         }
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, String> {
+    fn and(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.equality()?;
 
         while self.match_token(TokenType::And) {
@@ -221,14 +385,14 @@ impl Parser {
             let right = self.equality()?;
             expr = Expr::Logical {
                 left: Box::new(expr),
-                operator: operator,
+                operator,
                 right: Box::new(right)
             };
         }
         Ok(expr)
     }
 
-    fn equality(&mut self) -> Result<Expr, String> {
+    fn equality(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.comparison()?;
 
         while self.match_tokens(&[TokenType::BangEqual, TokenType::EqualEqual]) {
@@ -236,15 +400,15 @@ impl Parser {
             let rhs = self.comparison()?;
             expr = Expr::Binary {
                 left: Box::from(expr),
-                operator: operator,
+                operator,
                 right: Box::from(rhs),
             };
         }
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr, String> {
-        let mut expr = self.term()?;
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.range()?;
 
         while self.match_tokens(&[
             TokenType::Greater,
@@ -253,7 +417,7 @@ impl Parser {
             TokenType::LessEqual,
         ]) {
             let op = self.previous();
-            let rhs = self.term()?;
+            let rhs = self.range()?;
             expr = Expr::Binary {
                 left: Box::from(expr),
                 operator: op,
@@ -263,7 +427,24 @@ impl Parser {
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, String> {
+    /// `a..b` and `a..=b` bind looser than arithmetic, so `0..n + 1` is
+    /// `0..(n + 1)`, and don't chain.
+    fn range(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.term()?;
+
+        if self.match_tokens(&[TokenType::DotDot, TokenType::DotDotEqual]) {
+            let op = self.previous();
+            let rhs = self.term()?;
+            return Ok(Expr::Binary {
+                left: Box::from(expr),
+                operator: op,
+                right: Box::from(rhs),
+            });
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.factor()?;
 
         while self.match_tokens(&[
@@ -282,7 +463,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, String> {
+    fn factor(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.unary()?;
         while self.match_tokens(&[
             TokenType::Slash,
@@ -299,58 +480,177 @@ impl Parser {
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr, String> {
+    fn unary(&mut self) -> Result<Expr, ParseError> {
         if self.match_tokens(&[
             TokenType::Bang,
             TokenType::Minus,
         ]) {
             let op = self.previous();
-            let rhs = self.unary()?;
+            let rhs = self.nested(Self::unary)?;
             Ok(Expr::Unary {
                 operator: op,
                 right: Box::from(rhs),
             })
         } else {
-            self.primary()
+            self.call()
+        }
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_token(TokenType::LeftParen) {
+                    expr = self.finish_call(expr)?;
+            } else if self.match_token(TokenType::Dot) {
+                    let name = self.consume(TokenType::Identifier, "Expected property name after '.'")?;
+                expr = Expr::Get { object: Box::new(expr), name };
+            } else if self.match_token(TokenType::LeftBracket) {
+                    let index = self.expression()?;
+                let bracket = self.consume(TokenType::RightBracket, "Expected ']' after index")?;
+                expr = Expr::Index { object: Box::new(expr), bracket, index: Box::new(index) };
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, calee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = vec![];
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    return Err(ParseError::TooMany { span: self.peek().span, limit: 255, what: "arguments" });
+                }
+                arguments.push(self.expression()?);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
         }
+        let paren = self.consume(TokenType::RightParen, "Expected ')' after arguments")?;
+
+        Ok(Expr::Call {
+            calee: Box::new(calee),
+            paren,
+            arguments,
+        })
     }
 
-    fn primary(&mut self) -> Result<Expr, String> {
+    fn primary(&mut self) -> Result<Expr, ParseError> {
         let token = self.peek();
-        let result;
-        match token.token_type {
+        let result = match token.token_type {
             TokenType::LeftParen => {
                 self.advance();
                 let expr = self.expression()?;
-                self.consume(TokenType::RightParen, "Expected ')'")?;
-                result = Expr::Grouping { expression: Box::from(expr) };
+                let paren = self.consume(TokenType::RightParen, "Expected ')'")?;
+                Expr::Grouping { span: token.span.to(paren.span), expression: Box::from(expr) }
             },
             TokenType::False |
             TokenType::True |
             TokenType::Nil |
             TokenType::Number |
-            TokenType::Percent |
             TokenType::String => {
                 self.advance();
-                result = Expr::Literal { value: LiteralValue::from_token(token) };
+                Expr::Literal { span: token.span, value: self.literal(token)? }
             },
             TokenType::Identifier => {
                 self.advance();
-                result = Expr::Variable { name: self.previous() };
+                Expr::Variable { id: Expr::next_id(), name: self.previous() }
             },
-            _ => return Err("Expected expression".to_string()),
-        }
+            TokenType::This => {
+                self.advance();
+                Expr::This { id: Expr::next_id(), keyword: self.previous() }
+            },
+            TokenType::Super => {
+                let keyword = self.advance();
+                self.consume(TokenType::Dot, "Expected '.' after 'super'")?;
+                let method = self.consume(TokenType::Identifier, "Expected superclass method name")?;
+                Expr::Super { id: Expr::next_id(), keyword, method }
+            },
+            TokenType::LeftBracket => {
+                self.advance();
+                let mut elements = vec![];
+                while !self.check(TokenType::RightBracket) {
+                    elements.push(self.expression()?);
+                    if !self.match_token(TokenType::Comma) {
+                        break;
+                    }
+                }
+                let bracket = self.consume(TokenType::RightBracket, "Expected ']' after list elements")?;
+                Expr::List { span: token.span.to(bracket.span), bracket, elements }
+            },
+            TokenType::LeftBrace => {
+                self.advance();
+                let mut entries = vec![];
+                while !self.check(TokenType::RightBrace) {
+                    let key = self.expression()?;
+                    self.consume(TokenType::Colon, "Expected ':' after map key")?;
+                    let value = self.expression()?;
+                    entries.push((key, value));
+                    if !self.match_token(TokenType::Comma) {
+                        break;
+                    }
+                }
+                let brace = self.consume(TokenType::RightBrace, "Expected '}' after map entries")?;
+                Expr::Map { span: token.span.to(brace.span), brace, entries }
+            },
+            TokenType::Interpolation => self.interpolation()?,
+            _ => {
+                let (span, found) = self.found(&token);
+                return Err(ParseError::ExpectedExpression { span, found });
+            },
+        };
         Ok(result)
     }
 
-    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, String> {
+    fn literal(&self, token: Token) -> Result<LiteralValue, ParseError> {
+        let (span, lexeme) = (token.span, token.lexeme.clone());
+        LiteralValue::from_token(token).ok_or(ParseError::InvalidLiteral { span, lexeme })
+    }
+
+    /// The scanner splits `"a ${b} c"` into an `Interpolation` token for each
+    /// segment that precedes a `${`, the tokens of each embedded expression,
+    /// and a final `String` token for the tail.
+    fn interpolation(&mut self) -> Result<Expr, ParseError> {
+        let quote = self.advance();
+        let mut parts = vec![];
+        let mut segment = quote.clone();
+        loop {
+            let is_last = segment.token_type == TokenType::String;
+            let span = segment.span;
+            let text = self.literal(segment)?;
+            if text != LiteralValue::StringValue(String::new()) {
+                parts.push(Expr::Literal { span, value: text });
+            }
+            if is_last {
+                return Ok(Expr::Interpolation { span: quote.span.to(span), quote, parts });
+            }
+            parts.push(self.expression()?);
+            // segments that resume the string start at the `}` closing the expression
+            let resumes_string = self.check(TokenType::Interpolation) || self.check(TokenType::String);
+            segment = if resumes_string && self.peek().lexeme.starts_with('}') {
+                self.advance()
+            } else {
+                let next = self.peek();
+                let (span, found) = self.found(&next);
+                let message = "Expected '}' after interpolated expression".to_string();
+                return Err(ParseError::ExpectedToken { span, message, found });
+            };
+        }
+    }
+
+    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, ParseError> {
         let token = self.peek();
         if token.token_type == token_type {
             self.advance();
             let token = self.previous();
             Ok(token)
         } else {
-            Err(msg.to_string())
+            let (span, found) = self.found(&token);
+            Err(ParseError::ExpectedToken { span, message: msg.to_string(), found })
         }
     }
 
@@ -360,7 +660,7 @@ impl Parser {
 
     fn match_token(&mut self, token_type: TokenType) -> bool {
         if self.is_at_end() {
-            return false;
+            false
         } else {
             if self.peek().token_type == token_type {
                 self.advance();
@@ -392,13 +692,39 @@ impl Parser {
     }
 
     fn previous(&mut self) -> Token {
-        self.tokens[self.current - 1].clone()
+        self.tokens[self.current.saturating_sub(1)].clone()
     }
 
     fn is_at_end(&mut self) -> bool {
         self.peek().token_type == TokenType::Eof
     }
 
+    /// Runs `parse` a level deeper into the syntax tree, failing once the
+    /// program nests more than `MAX_NESTING` levels.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.depth == MAX_NESTING {
+            return Err(ParseError::TooMany { span: self.peek().span, limit: MAX_NESTING, what: "levels of nesting" });
+        }
+        self.depth += 1;
+        let result = limits::deep(|| parse(self));
+        self.depth -= 1;
+        result
+    }
+
+    /// Where to report finding `token` instead of what the grammar wanted,
+    /// and its lexeme. Running out of input is reported just past the last
+    /// real token, where the missing piece belongs.
+    fn found(&self, token: &Token) -> (Span, Option<String>) {
+        if token.token_type == TokenType::Eof {
+            let span = self.tokens.iter().rev()
+                .find(|token| token.token_type != TokenType::Eof)
+                .map_or(token.span, |token| token.span.after());
+            (span, None)
+        } else {
+            (token.span, Some(token.lexeme.clone()))
+        }
+    }
+
     fn syncronize(&mut self) {
         self.advance();
 
@@ -421,6 +747,7 @@ impl Parser {
     }
 }
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -432,25 +759,25 @@ mod tests {
             token_type: TokenType::Number,
             lexeme: "1".to_string(),
             literal: Some(LiteralValue::IntValue(1)),
-            line_number: 0
+            span: Span::default(),
         };
         let plus = Token {
             token_type: TokenType::Plus,
             lexeme: "+".to_string(),
             literal: None,
-            line_number: 0
+            span: Span::default(),
         };
         let two = Token {
             token_type: TokenType::Number,
             lexeme: "2".to_string(),
             literal: Some(LiteralValue::IntValue(2)),
-            line_number: 0
+            span: Span::default(),
         };
         let semicolon = Token {
             token_type: TokenType::Semicolon,
             lexeme: ";".to_string(),
             literal: None,
-            line_number: 0
+            span: Span::default(),
         };
         let tokens = vec![
             one, plus, two, semicolon
@@ -487,4 +814,117 @@ mod tests {
         let string_expression = parsed_expr[0].to_string();
         assert_eq!(string_expression, "(== 1 (group (+ 2 2)))");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_function_declaration_and_call() {
+        let source = "fun add(a, b) { return a + b; } add(1, 2);";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(scanner.tokens);
+        let parsed = parser.parse().unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].to_string(), "(fun add (a b) (return (+ (var a) (var b))))");
+        assert!(parsed[1].to_string().starts_with("(call (var add)"));
+    }
+
+    #[test]
+    fn test_map_literal_vs_block() {
+        let source = "{\"a\": 1, 2: [3]}; {} { var x = {}; }";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(scanner.tokens);
+        let parsed = parser.parse().unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].to_string(), "(map (\"a\" 1) (2 (list 3)))");
+        assert_eq!(parsed[1].to_string(), "(block)");
+        assert_eq!(parsed[2].to_string(), "(block (var x (map)))");
+    }
+
+    #[test]
+    fn test_range_and_for_in() {
+        let source = "for (x in 0..n + 1) print x; for (var i = 0; i < 2; i = i + 1) print i;";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(scanner.tokens);
+        let parsed = parser.parse().unwrap();
+        assert_eq!(parsed[0].to_string(), "(for-in x (.. 0 (+ (var n) 1)) (print (var x)))");
+        assert_eq!(parsed[1].to_string(), "(for (var i 0) (< (var i) 2) (= i (+ (var i) 1)) (print (var i)))");
+    }
+
+    #[test]
+    fn test_break_continue_and_labels() {
+        let source = "outer: while (true) { break outer; continue; }";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(scanner.tokens);
+        let parsed = parser.parse().unwrap();
+        assert_eq!(parsed[0].to_string(), "(label outer (while true (block (break outer) (continue))))");
+
+        let mut scanner = Scanner::new("outer: print 1;");
+        scanner.scan_tokens().unwrap();
+        assert!(Parser::new(scanner.tokens).parse().is_err());
+    }
+
+    #[test]
+    fn test_labeled_loop_at_start_of_block() {
+        let source = "while (n < 1) { inner: while (true) { break inner; } n = n + 1; } { x: for (;;) {} } { x: 1 };";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let parsed = Parser::new(scanner.tokens).parse().unwrap();
+        assert_eq!(
+            parsed[0].to_string(),
+            "(while (< (var n) 1) (block (label inner (while true (block (break inner)))) (= n (+ (var n) 1))))"
+        );
+        assert!(parsed[1].to_string().starts_with("(block (label x"), "{}", parsed[1]);
+        assert!(!parsed[2].to_string().starts_with("(block"), "{}", parsed[2]);
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let source = r#"print "x=${x + 1}, s=${"in${y}"}";"#;
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(scanner.tokens);
+        let parsed = parser.parse().unwrap();
+        assert_eq!(
+            parsed[0].to_string(),
+            r#"(print (interpolate "x=" (+ (var x) 1) ", s=" (interpolate "in" (var y))))"#
+        );
+    }
+
+    #[test]
+    fn test_errors_and_nodes_have_spans() {
+        let parse = |source: &str| {
+            let mut scanner = Scanner::new(source);
+            scanner.scan_tokens().unwrap();
+            Parser::new(scanner.tokens).parse()
+        };
+        let error = |source: &str| {
+            let error = parse(source).unwrap_err().remove(0).diagnostic();
+            format!("{} ({})", error, error.labels[0].message)
+        };
+        assert_eq!(error("var = 1;"), "Line 1, column 5: Expected variable name (found '=')");
+        assert_eq!(error("print (1;"), "Line 1, column 9: Expected ')' (found ';')");
+        assert_eq!(error("print 1"), "Line 1, column 8: Expected ';' after value. (found end of input)");
+        assert_eq!(error("1 + 2 = 3;"), "Line 1, column 7: Invalid assignment target. (can't assign to this)");
+
+        let parsed = parse("x = (1 +\n  2) * y;").unwrap();
+        let span = parsed[0].span();
+        assert_eq!((span.line, span.column, span.end_line, span.end_column), (1, 1, 2, 9));
+    }
+
+    #[test]
+    fn test_malformed_tokens_are_errors() {
+        let mut scanner = Scanner::new("print %;");
+        scanner.scan_tokens().unwrap();
+        let errors = Parser::new(scanner.tokens).parse().unwrap_err();
+        assert!(matches!(errors[0], ParseError::ExpectedExpression { .. }));
+
+        let mut scanner = Scanner::new("print 1;");
+        scanner.scan_tokens().unwrap();
+        let mut tokens = scanner.tokens;
+        tokens[1].literal = None;
+        let errors = Parser::new(tokens).parse().unwrap_err();
+        assert_eq!(errors[0].to_string(), "Line 1, column 7: Invalid literal '1'");
+    }
+}
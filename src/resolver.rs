@@ -0,0 +1,422 @@
+use crate::ast::Expr;
+use crate::error::CompileError;
+use crate::interpreter::Interpreter;
+use crate::limits;
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Walks the parsed program once before it runs, telling the interpreter how
+/// many scopes out each local variable lives and rejecting programs that are
+/// only detectable statically.
+pub struct Resolver<'a> {
+    interpreter: &'a mut Interpreter,
+    /// One map per local scope; the flag is `true` once the variable's
+    /// initializer has been resolved.
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    /// Enclosing loops of the current function, innermost last. Labeled
+    /// loops get an extra entry holding their label.
+    loops: Vec<Option<String>>,
+    /// Ids resolved in the top-level statements, then in each function body
+    /// being resolved, innermost last.
+    resolved_ids: Vec<Vec<usize>>,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        Self {
+            interpreter,
+            scopes: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loops: vec![],
+            resolved_ids: vec![vec![]],
+            errors: vec![],
+        }
+    }
+
+    pub fn resolve(&mut self, stmts: &[Stmt]) -> Result<(), Vec<CompileError>> {
+        self.resolve_statements(stmts);
+        let top_level_ids = std::mem::take(&mut self.resolved_ids[0]);
+        self.interpreter.own_resolved(None, top_level_ids);
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn resolve_statements(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        limits::deep(|| self.resolve_stmt_node(stmt))
+    }
+
+    fn resolve_stmt_node(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression } => self.resolve_expr(expression),
+            Stmt::Print { keyword: _, expression } => self.resolve_expr(expression),
+            Stmt::Var { name, initializer } => {
+                self.declare(name);
+                self.resolve_expr(initializer);
+                self.define(name);
+            },
+            Stmt::Block { span: _, statements } => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            },
+            Stmt::IfStmt { predicate, then, els } => {
+                self.resolve_expr(predicate);
+                self.resolve_stmt(then);
+                if let Some(els) = els {
+                    self.resolve_stmt(els);
+                }
+            },
+            Stmt::WhileStmt { condition, body } => {
+                self.resolve_expr(condition);
+                self.loops.push(None);
+                self.resolve_stmt(body);
+                self.loops.pop();
+            },
+            Stmt::ForStmt { var_decl, expr_stmt, condition, increment, body } => {
+                self.begin_scope();
+                if let Some(var_decl) = var_decl {
+                    self.resolve_stmt(var_decl);
+                }
+                if let Some(expr_stmt) = expr_stmt {
+                    self.resolve_stmt(expr_stmt);
+                }
+                if let Some(condition) = condition {
+                    self.resolve_expr(condition);
+                }
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+                self.loops.push(None);
+                self.resolve_stmt(body);
+                self.loops.pop();
+                self.end_scope();
+            },
+            Stmt::ForInStmt { variable, iterable, body } => {
+                self.resolve_expr(iterable);
+                self.begin_scope();
+                self.declare(variable);
+                self.define(variable);
+                self.loops.push(None);
+                self.resolve_stmt(body);
+                self.loops.pop();
+                self.end_scope();
+            },
+            Stmt::Break { keyword, label } | Stmt::Continue { keyword, label } => {
+                self.resolve_loop_jump(keyword, label.as_ref());
+            },
+            Stmt::Labeled { label, body } => {
+                self.loops.push(Some(label.lexeme.clone()));
+                self.resolve_stmt(body);
+                self.loops.pop();
+            },
+            Stmt::Function { name, params, body } => {
+                self.declare(name);
+                self.define(name);
+                self.resolve_function(params, body, FunctionType::Function);
+            },
+            Stmt::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    self.errors.push(CompileError::TopLevelReturn { span: keyword.span });
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.errors.push(CompileError::ReturnFromInitializer { span: keyword.span });
+                    }
+                    self.resolve_expr(value);
+                }
+            },
+            Stmt::Class { name, superclass, methods } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expr(superclass);
+                    self.begin_scope();
+                    self.define_name("super");
+                }
+
+                self.begin_scope();
+                self.define_name("this");
+                for method in methods {
+                    if let Stmt::Function { name: method_name, params, body } = method {
+                        let function_type = if method_name.lexeme == "init" {
+                            FunctionType::Initializer
+                        } else {
+                            FunctionType::Method
+                        };
+                        self.resolve_function(params, body, function_type);
+                    }
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.current_class = enclosing_class;
+            },
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        limits::deep(|| self.resolve_expr_node(expr))
+    }
+
+    fn resolve_expr_node(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { id, name, value } => {
+                self.resolve_expr(value);
+                self.resolve_local(*id, name);
+            },
+            Expr::Binary { left, operator: _, right } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            },
+            Expr::Grouping { span: _, expression } => self.resolve_expr(expression),
+            Expr::Literal { span: _, value: _ } => (),
+            Expr::Unary { operator: _, right } => self.resolve_expr(right),
+            Expr::Variable { id, name } => {
+                if self.scopes.last().and_then(|scope| scope.get(&name.lexeme)) == Some(&false) {
+                    self.errors.push(CompileError::ReadInOwnInitializer { span: name.span, name: name.lexeme.clone() });
+                }
+                self.resolve_local(*id, name);
+            },
+            Expr::Logical { left, operator: _, right } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            },
+            Expr::Call { calee, paren: _, arguments } => {
+                self.resolve_expr(calee);
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+            },
+            Expr::Get { object, name: _ } => self.resolve_expr(object),
+            Expr::Set { object, name: _, value } => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            },
+            Expr::This { id, keyword } => {
+                if self.current_class == ClassType::None {
+                    self.errors.push(CompileError::ThisOutsideClass { span: keyword.span });
+                    return;
+                }
+                self.resolve_local(*id, keyword);
+            },
+            Expr::Super { id, keyword, method: _ } => {
+                match self.current_class {
+                    ClassType::None => self.errors.push(CompileError::SuperOutsideClass { span: keyword.span }),
+                    ClassType::Class => self.errors.push(CompileError::SuperWithoutSuperclass { span: keyword.span }),
+                    ClassType::Subclass => (),
+                }
+                self.resolve_local(*id, keyword);
+            },
+            Expr::List { span: _, bracket: _, elements } => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            },
+            Expr::Map { span: _, brace: _, entries } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            },
+            Expr::Index { object, bracket: _, index } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            },
+            Expr::IndexSet { object, bracket: _, index, value } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            },
+            Expr::Interpolation { span: _, quote: _, parts } => {
+                for part in parts {
+                    self.resolve_expr(part);
+                }
+            },
+        }
+    }
+
+    fn resolve_loop_jump(&mut self, keyword: &Token, label: Option<&Token>) {
+        match label {
+            None if self.loops.is_empty() => {
+                self.errors.push(CompileError::JumpOutsideLoop { span: keyword.span, keyword: keyword.lexeme.clone() });
+            },
+            Some(label) if !self.loops.contains(&Some(label.lexeme.clone())) => {
+                self.errors.push(CompileError::UnknownLoopLabel { span: label.span, label: label.lexeme.clone() });
+            },
+            _ => (),
+        }
+    }
+
+    fn resolve_function(&mut self, params: &[Token], body: &Rc<Vec<Stmt>>, function_type: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+        // Loops outside the function can't be broken out of from inside it.
+        let enclosing_loops = std::mem::take(&mut self.loops);
+        self.resolved_ids.push(vec![]);
+
+        self.begin_scope();
+        for param in params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(body);
+        self.end_scope();
+
+        let ids = self.resolved_ids.pop().unwrap_or_default();
+        self.interpreter.own_resolved(Some(body), ids);
+        self.loops = enclosing_loops;
+        self.current_function = enclosing_function;
+    }
+
+    fn resolve_local(&mut self, id: usize, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                self.interpreter.resolve(id, depth);
+                if let Some(ids) = self.resolved_ids.last_mut() {
+                    ids.push(id);
+                }
+                return;
+            }
+        }
+        // Not found in any local scope: the interpreter treats it as a global.
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let already_declared = match self.scopes.last_mut() {
+            Some(scope) => scope.insert(name.lexeme.clone(), false).is_some(),
+            None => false,
+        };
+        if already_declared {
+            self.errors.push(CompileError::AlreadyDeclared { span: name.span, name: name.lexeme.clone() });
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        self.define_name(&name.lexeme);
+    }
+
+    fn define_name(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), true);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LoxError;
+    use crate::scanner::Scanner;
+    use crate::parser::Parser;
+
+    fn resolve(source: &str) -> Result<(), String> {
+        let resolve = || -> Result<(), LoxError> {
+            let mut scanner = Scanner::new(source);
+            scanner.scan_tokens()?;
+            let mut parser = Parser::new(scanner.tokens);
+            let stmts = parser.parse()?;
+            let mut interpreter = Interpreter::new();
+            let mut resolver = Resolver::new(&mut interpreter);
+            Ok(resolver.resolve(&stmts)?)
+        };
+        resolve().map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn local_read_in_own_initializer() {
+        let result = resolve("{ var a = 1; { var a = a; } }");
+        assert!(result.unwrap_err().contains("own initializer"));
+    }
+
+    #[test]
+    fn global_read_in_own_initializer_is_allowed() {
+        assert!(resolve("var a = 1; var a = a;").is_ok());
+    }
+
+    #[test]
+    fn duplicate_declaration_in_block() {
+        let result = resolve("fun f() { var a = 1; var a = 2; }");
+        assert!(result.unwrap_err().contains("Already a variable"));
+        assert!(resolve("fun f(a, a) {}").is_err());
+    }
+
+    #[test]
+    fn top_level_return() {
+        let result = resolve("return 1;");
+        assert!(result.unwrap_err().contains("top-level"));
+        assert!(resolve("fun f() { return 1; }").is_ok());
+    }
+
+    #[test]
+    fn return_value_from_initializer() {
+        assert!(resolve("class A { init() { return 1; } }").is_err());
+        assert!(resolve("class A { init() { return; } }").is_ok());
+    }
+
+    #[test]
+    fn this_and_super_outside_of_class() {
+        assert!(resolve("print this;").is_err());
+        assert!(resolve("fun f() { return super.x; }").is_err());
+        assert!(resolve("class A { m() { return super.m(); } }").is_err());
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let result = resolve("return 1; print this;").unwrap_err();
+        assert_eq!(result.lines().count(), 2);
+    }
+
+    #[test]
+    fn break_and_continue_outside_of_loop() {
+        assert!(resolve("break;").unwrap_err().contains("outside of a loop"));
+        assert!(resolve("while (true) { fun f() { continue; } }").is_err());
+        assert!(resolve("a: while (true) { while (true) break b; }").unwrap_err().contains("labeled 'b'"));
+        assert!(resolve("a: while (true) { for (x in 0..1) continue a; }").is_ok());
+    }
+}
@@ -1,26 +1,39 @@
+use crate::error::ScanError;
+use crate::span::Span;
+use num_bigint::BigInt;
 use std::collections::HashMap;
+use std::fmt;
+use unicode_xid::UnicodeXID;
 
 fn is_digit(ch: char) -> bool {
-    (ch as u8) >= '0' as u8 && ch as u8 <= '9' as u8
+    ch.is_ascii_digit()
 }
 
+/// Identifiers follow Unicode's XID rules, plus `_` anywhere.
 fn is_alpha(ch: char) -> bool {
-    (ch as u8 >= 'a' as u8 && ch as u8 <= 'z' as u8) || (ch as u8 >= 'A' as u8 && ch as u8 <= 'Z' as u8) || ch == '_'
+    ch.is_xid_start() || ch == '_'
 }
 
 fn is_alpha_numeric(ch: char) -> bool {
-    is_alpha(ch) || is_digit(ch)
+    ch.is_xid_continue()
+}
+
+fn invalid_escape(span: Span, message: String) -> ScanError {
+    ScanError::InvalidEscape { span, message }
 }
 
 fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
     HashMap::from([
         ("and", TokenType::And),
+        ("break", TokenType::Break),
         ("class", TokenType::Class),
+        ("continue", TokenType::Continue),
         ("else", TokenType::Else),
         ("false", TokenType::False),
         ("for", TokenType::For),
         ("fun", TokenType::Fun),
         ("if", TokenType::If),
+        ("in", TokenType::In),
         ("nil", TokenType::Nil),
         ("or", TokenType::Or),
         ("print", TokenType::Print),
@@ -33,12 +46,29 @@ fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
     ])
 }
 
+/// A point in the source: byte offset plus the 1-based line and column of
+/// the character found there.
+#[derive(Clone, Copy)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+/// Turns source text into tokens. `current` is a byte offset into `source`
+/// that always sits on a char boundary; `column` counts the characters
+/// consumed so far on the current line.
 pub struct Scanner {
     source: String,
     pub tokens: Vec<Token>,
-    start: usize,
+    /// Where the token being scanned begins.
+    start: Position,
     current: usize,
     line: usize,
+    column: usize,
+    /// One entry per `${` still open, counting the `{` opened inside it so
+    /// the matching `}` resumes the string.
+    interpolations: Vec<usize>,
 
     keywords: HashMap<&'static str, TokenType>,
 }
@@ -48,54 +78,79 @@ impl Scanner {
         Self {
             source: source.to_string(),
             tokens: vec![],
-            start: 0,
+            start: Position { offset: 0, line: 1, column: 1 },
             current: 0,
             line: 1,
+            column: 0,
+            interpolations: vec![],
             keywords: get_keywords_hashmap(),
         }
     }
 
-    pub fn scan_tokens(self: &mut Self) -> Result<(), String> {
+    pub fn scan_tokens(&mut self) -> Result<(), Vec<ScanError>> {
         let mut errors = vec![];
         while !self.is_at_end() {
-            self.start = self.current;
+            self.start = self.position();
             match self.scan_token() {
                 Ok(_) => (),
                 Err(msg) => errors.push(msg),
             }
             // self.scan_tokens()?;
         }
+        if !self.interpolations.is_empty() {
+            errors.push(ScanError::UnterminatedInterpolation { span: self.span_from(self.position()) });
+        }
         self.tokens.push(Token {
             token_type: TokenType::Eof,
             lexeme: "".to_string(),
             literal: None,
-            line_number: self.line
+            span: self.span_from(self.position()),
         });
-        if errors.len() > 0 {
-            let mut joined = "".to_string();
-            errors.iter().for_each(|msg| {
-                joined.push_str(&msg);
-                joined.push_str("\n");
-            });
-            return Err(joined);
+        if !errors.is_empty() {
+            return Err(errors);
         }
         Ok(())
     }
     
-    fn is_at_end(self: &Self) -> bool {
+    fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
-    fn scan_token(self: &mut Self) -> Result<(), String> {
+    fn scan_token(&mut self) -> Result<(), ScanError> {
         let c = self.advance();
 
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace);
+            },
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string()?;
+                },
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(TokenType::RightBrace);
+                },
+                None => self.add_token(TokenType::RightBrace),
+            },
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
+            ':' => self.add_token(TokenType::Colon),
+            '.' => {
+                let token = if self.char_match('.') {
+                    if self.char_match('=') { TokenType::DotDotEqual } else { TokenType::DotDot }
+                } else {
+                    TokenType::Dot
+                };
+                self.add_token(token);
+            },
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             '%' => self.add_token(TokenType::Percent),
@@ -146,9 +201,7 @@ impl Scanner {
                 }
             },
             ' ' | '\r' | '\t' => {},
-            '\n' => {
-                self.line += 1
-            },
+            '\n' => self.new_line(),
             '"' => self.string()?,
             c => {
                 if is_digit(c) {
@@ -156,18 +209,18 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    return Err(format!("Unrecognized char at line {}: {}", self.line, c));
+                    return Err(ScanError::UnrecognizedCharacter { span: self.span_from(self.start), character: c });
                 }
             },
         }
         Ok(())
     }
 
-    fn identifier(self: &mut Self) {
+    fn identifier(&mut self) {
         while is_alpha_numeric(self.peek()) {
             self.advance();
         }
-        let substring = &self.source[self.start..self.current];
+        let substring = &self.source[self.start.offset..self.current];
         if let Some(&t_type) = self.keywords.get(substring) {
             self.add_token(t_type);
         } else {
@@ -175,88 +228,198 @@ impl Scanner {
         }
     }
 
-    fn number(self: &mut Self) -> Result<(), String>{
+    fn number(&mut self) -> Result<(), ScanError> {
         while is_digit(self.peek()) {
             self.advance();
         }
+        let mut is_float = false;
         if self.peek() == '.' && is_digit(self.peek_next()) {
+            is_float = true;
             self.advance();
 
             while is_digit(self.peek()) {
                 self.advance();
             }
         }
-        let substring = &self.source[self.start..self.current];
-        let value = substring.parse::<f64>();
-        match value {
-            Ok(value) => self.add_token_lit(TokenType::Number, Some(LiteralValue::FloatValue(value))),
-            Err(_) => return Err(format!("Could not parse: {}", substring)),
+        let substring = &self.source[self.start.offset..self.current];
+        let literal = if is_float {
+            substring.parse::<f64>().map(LiteralValue::FloatValue).ok()
+        } else {
+            substring.parse::<i64>().map(LiteralValue::IntValue)
+                .or_else(|_| substring.parse::<BigInt>().map(LiteralValue::BigIntValue))
+                .ok()
+        };
+        match literal {
+            Some(literal) => self.add_token_lit(TokenType::Number, Some(literal)),
+            None => return Err(ScanError::InvalidNumber {
+                span: self.span_from(self.start),
+                text: substring.to_string(),
+            }),
         }
         Ok(())
     }
 
-    fn peek_next(self: &mut Self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        return self.source.chars().nth(self.current + 1).unwrap();
+    fn peek_next(&self) -> char {
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
-    fn string(self: &mut Self) -> Result<(), String>{
+    /// Scans string contents up to the closing quote, or up to a `${`. In
+    /// the latter case the text so far becomes an `Interpolation` token and
+    /// scanning goes on with the embedded expression; the `}` that ends it
+    /// calls back in here for the rest of the string.
+    fn string(&mut self) -> Result<(), ScanError> {
+        let mut value = String::new();
+        // Only the first bad escape is reported, but the rest of the string
+        // is still consumed so scanning resumes after the closing quote.
+        let mut error = None;
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            if self.peek() == '$' && self.peek_next() == '{' {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                if let Some(msg) = error {
+                    return Err(msg);
+                }
+                self.add_token_lit(TokenType::Interpolation, Some(LiteralValue::StringValue(value)));
+                return Ok(());
+            }
+            match self.advance() {
+                '\\' => match self.escape() {
+                    Ok(ch) => value.push(ch),
+                    Err(msg) => {
+                        error.get_or_insert(msg);
+                    },
+                },
+                '\n' => {
+                    self.new_line();
+                    value.push('\n');
+                },
+                ch => value.push(ch),
             }
-            self.advance();
         }
         if self.is_at_end() {
-            return Err("Unterminated string".to_string());
+            return Err(ScanError::UnterminatedString { span: self.span_from(self.start) });
         }
         self.advance();
-        let value = &self.source[self.start + 1..self.current - 1];
-            // .collect::<String>();
+        if let Some(msg) = error {
+            return Err(msg);
+        }
 
-        self.add_token_lit(TokenType::String, Some(LiteralValue::StringValue(value.to_string())));
+        self.add_token_lit(TokenType::String, Some(LiteralValue::StringValue(value)));
         Ok(())
     }
 
-    fn peek(self: &mut Self) -> char {
+    /// Decodes the escape sequence after a `\` inside a string literal.
+    fn escape(&mut self) -> Result<char, ScanError> {
+        // the backslash has just been consumed
+        let start = Position { offset: self.current - 1, line: self.line, column: self.column };
         if self.is_at_end() {
-            return '\0';
+            return Err(invalid_escape(self.span_from(start), "Unterminated escape sequence".to_string()));
+        }
+        match self.advance() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '"' => Ok('"'),
+            '$' => Ok('$'),
+            'u' => self.unicode_escape(start),
+            ch => {
+                let span = self.span_from(start);
+                if ch == '\n' {
+                    self.new_line();
+                }
+                Err(invalid_escape(span, format!("Unknown escape sequence '\\{}'", ch.escape_default())))
+            },
         }
-        self.source.chars().nth(self.current).unwrap()
     }
 
-    fn char_match(self: &mut Self, ch: char) -> bool {
-        if self.is_at_end() {
-            return false;
+    /// `\u{...}`: one to six hex digits naming a Unicode scalar value.
+    fn unicode_escape(&mut self, start: Position) -> Result<char, ScanError> {
+        if !self.char_match('{') {
+            return Err(invalid_escape(self.span_from(start), "Expected '{' after '\\u'".to_string()));
+        }
+        let mut digits = String::new();
+        while self.peek().is_ascii_hexdigit() {
+            digits.push(self.advance());
         }
-        if self.source.chars().nth(self.current).unwrap() != ch {
+        if !self.char_match('}') {
+            let message = "Unicode escape must be hex digits closed by '}'".to_string();
+            return Err(invalid_escape(self.span_from(start), message));
+        }
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(invalid_escape(self.span_from(start), "Unicode escape must have 1 to 6 hex digits".to_string()));
+        }
+        u32::from_str_radix(&digits, 16).ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| invalid_escape(
+                self.span_from(start),
+                format!("'\\u{{{}}}' is not a valid Unicode code point", digits),
+            ))
+    }
+
+    /// Called after consuming a `\n`.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.column = 0;
+    }
+
+    /// The position of the next character to be consumed.
+    fn position(&self) -> Position {
+        Position { offset: self.current, line: self.line, column: self.column + 1 }
+    }
+
+    /// The span from `start` up to the last character consumed.
+    fn span_from(&self, start: Position) -> Span {
+        Span {
+            start: start.offset,
+            end: self.current,
+            line: start.line,
+            column: start.column,
+            end_line: self.line,
+            end_column: self.column + 1,
+        }
+    }
+
+    fn peek(&self) -> char {
+        self.source[self.current..].chars().next().unwrap_or('\0')
+    }
+
+    fn char_match(&mut self, ch: char) -> bool {
+        if self.is_at_end() || self.peek() != ch {
             return false;
-        } else {
-            self.current += 1;
-            return true;
         }
+        self.current += ch.len_utf8();
+        self.column += 1;
+        true
     }
 
-    fn advance(self: &mut Self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+    /// Consumes the next character; at the end of input this returns `'\0'`
+    /// and stays put.
+    fn advance(&mut self) -> char {
+        let c = self.peek();
+        if !self.is_at_end() {
+            self.current += c.len_utf8();
+            self.column += 1;
+        }
         c
     }
 
-    fn add_token(self: &mut Self, token_type: TokenType) {
+    fn add_token(&mut self, token_type: TokenType) {
         self.add_token_lit(token_type, None);
     }
 
-    fn add_token_lit(self: &mut Self, token_type: TokenType, literal: Option<LiteralValue>) {
-        let text = &self.source[self.start..self.current];
+    fn add_token_lit(&mut self, token_type: TokenType, literal: Option<LiteralValue>) {
+        let text = &self.source[self.start.offset..self.current];
 
         self.tokens.push(Token {
-            token_type: token_type,
+            token_type,
             lexeme: text.to_string(),
-            literal: literal,
-            line_number: self.line,
+            literal,
+            span: self.span_from(self.start),
         })
     }
 }
@@ -268,8 +431,13 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
+    DotDot,
+    DotDotEqual,
     Minus,
     Plus,
     Percent,
@@ -289,15 +457,21 @@ pub enum TokenType {
 
     Identifier,
     String,
+    /// A string segment followed by `${`; the rest of the string comes
+    /// after the embedded expression.
+    Interpolation,
     Number,
 
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -311,10 +485,11 @@ pub enum TokenType {
     Eof,
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::enum_variant_names)]
 #[derive(Debug, Clone)]
 pub enum LiteralValue {
     IntValue(i64),
+    BigIntValue(BigInt),
     FloatValue(f64),
     StringValue(String),
     IdentifierValue(String),
@@ -326,144 +501,223 @@ pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Option<LiteralValue>,
-    pub line_number: usize,
+    pub span: Span,
 }
 
-impl Token {
-    pub fn to_string(self: &Self) -> String {
-        format!("{:?} {} {:?}", self.token_type, self.lexeme, self.literal)
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {} {:?}", self.token_type, self.lexeme, self.literal)
     }
 }
 
 
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-
-//     #[test]
-//     fn handle_one_char_tokens() {
-//         let source = "(( )) }{";
-//         let mut scanner = Scanner::new(source);
-//         scanner.scan_tokens();
-//         assert_eq!(scanner.tokens.len(), 7);
-//         assert_eq!(scanner.tokens[0].token_type, TokenType::LeftParen);
-//         assert_eq!(scanner.tokens[1].token_type, TokenType::LeftParen);
-//         assert_eq!(scanner.tokens[2].token_type, TokenType::RightParen);
-//         assert_eq!(scanner.tokens[3].token_type, TokenType::RightParen);
-//         assert_eq!(scanner.tokens[4].token_type, TokenType::RightBrace);
-//         assert_eq!(scanner.tokens[5].token_type, TokenType::LeftBrace);
-//         assert_eq!(scanner.tokens[6].token_type, TokenType::Eof);
-//     }
-
-//     #[test]
-//     fn handle_two_char_tokens() {
-//         let source = "! != == >=";
-//         let mut scanner = Scanner::new(source);
-//         scanner.scan_tokens().unwrap();
-//         assert_eq!(scanner.tokens.len(), 5);
-//         assert_eq!(scanner.tokens[0].token_type, TokenType::Bang);
-//         assert_eq!(scanner.tokens[1].token_type, TokenType::BangEqual);
-//         assert_eq!(scanner.tokens[2].token_type, TokenType::EqualEqual);
-//         assert_eq!(scanner.tokens[3].token_type, TokenType::GreaterEqual);
-//         assert_eq!(scanner.tokens[4].token_type, TokenType::Eof);
-//     }
-
-//     #[test]
-//     fn handle_string_lit() {
-//         let source = r#""ABC""#;
-//         let mut scanner = Scanner::new(source);
-//         scanner.scan_tokens().unwrap();
-//         assert_eq!(scanner.tokens.len(), 2);
-//         assert_eq!(scanner.tokens[0].token_type, TokenType::String);
-//         match scanner.tokens[0].literal.as_ref().unwrap() {
-//             LiteralValue::StringValue(value) => assert_eq!(value, &"ABC"),
-//             _ => panic!("Incorrect literal value"),
-//         }
-//         assert_eq!(scanner.tokens[1].token_type, TokenType::Eof);
-//     }
-
-//     #[test]
-//     fn handle_string_lit_unterminated() {
-//         let source = r#""ABC"#;
-//         let mut scanner = Scanner::new(source);
-//         let result = scanner.scan_tokens();
-//         match result {
-//             Err(_) => (),
-//             _ => panic!("Should have failed"),
-//         }
-//     }
-
-//     #[test]
-//     fn handle_string_lit_multiline() {
-//         let source = "\"ABC\ndef\"";
-//         let mut scanner = Scanner::new(source);
-//         scanner.scan_tokens().unwrap();
-//         assert_eq!(scanner.tokens.len(), 2);
-//         assert_eq!(scanner.tokens[0].token_type, TokenType::String);
-//         match scanner.tokens[0].literal.as_ref().unwrap() {
-//             LiteralValue::StringValue(value) => assert_eq!(value, &"ABC\ndef"),
-//             _ => panic!("Incorrect literal value"),
-//         }
-//         assert_eq!(scanner.tokens[1].token_type, TokenType::Eof);
-//     }
-
-//     #[test]
-//     fn number_litterals() {
-//         let source = "123.123\n321.0\n5";
-//         let mut scanner = Scanner::new(source);
-//         scanner.scan_tokens().unwrap();
-//         assert_eq!(scanner.tokens.len(), 4);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_one_char_tokens() {
+        let source = "(( )) }{";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        assert_eq!(scanner.tokens.len(), 7);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::LeftParen);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::LeftParen);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::RightParen);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::RightParen);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::RightBrace);
+        assert_eq!(scanner.tokens[5].token_type, TokenType::LeftBrace);
+        assert_eq!(scanner.tokens[6].token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn handle_two_char_tokens() {
+        let source = "! != == >=";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        assert_eq!(scanner.tokens.len(), 5);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::Bang);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::BangEqual);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::EqualEqual);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::GreaterEqual);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn handle_string_lit() {
+        let source = r#""ABC""#;
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        assert_eq!(scanner.tokens.len(), 2);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::String);
+        match scanner.tokens[0].literal.as_ref().unwrap() {
+            LiteralValue::StringValue(value) => assert_eq!(value, &"ABC"),
+            _ => panic!("Incorrect literal value"),
+        }
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn handle_string_lit_unterminated() {
+        let source = r#""ABC"#;
+        let mut scanner = Scanner::new(source);
+        let result = scanner.scan_tokens();
+        match result {
+            Err(_) => (),
+            _ => panic!("Should have failed"),
+        }
+    }
+
+    #[test]
+    fn handle_string_lit_multiline() {
+        let source = "\"ABC\ndef\"";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        assert_eq!(scanner.tokens.len(), 2);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::String);
+        match scanner.tokens[0].literal.as_ref().unwrap() {
+            LiteralValue::StringValue(value) => assert_eq!(value, &"ABC\ndef"),
+            _ => panic!("Incorrect literal value"),
+        }
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn number_litterals() {
+        let source = "123.123\n321.0\n5";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        assert_eq!(scanner.tokens.len(), 4);
         
-//         for i in 0..3 {
-//             assert_eq!(scanner.tokens[i].token_type, TokenType::Number)
-//         }
-//         match scanner.tokens[0].literal {
-//             Some(LiteralValue::FloatValue(value)) => assert_eq!(value, 123.123),
-//             _ => panic!("Incorrect literal value"),
-//         }
-//         match scanner.tokens[1].literal {
-//             Some(LiteralValue::FloatValue(value)) => assert_eq!(value, 321.0),
-//             _ => panic!("Incorrect literal value"),
-//         }
-//         match scanner.tokens[2].literal {
-//             Some(LiteralValue::FloatValue(value)) => assert_eq!(value, 5.0),
-//             _ => panic!("Incorrect literal value"),
-//         }
-//     }
-
-//     #[test]
-//     fn get_identifier() {
-//         let source = "this_is_a_var = 12;";
-//         let mut scanner = Scanner::new(source);
-//         scanner.scan_tokens().unwrap();
-
-//         assert_eq!(scanner.tokens.len(), 5);
-//         assert_eq!(scanner.tokens[0].token_type, TokenType::Identifier);
-//         assert_eq!(scanner.tokens[1].token_type, TokenType::Equal);
-//         assert_eq!(scanner.tokens[2].token_type, TokenType::Number);
-//         assert_eq!(scanner.tokens[3].token_type, TokenType::Semicolon);
-//         assert_eq!(scanner.tokens[4].token_type, TokenType::Eof);
-//     }
-
-//     #[test]
-//     fn get_keywords() {
-//         let source = "var this_is_var = 12;\nwhile true { print 3 };";
-//         let mut scanner = Scanner::new(source);
-//         scanner.scan_tokens().unwrap();
-
-//         assert_eq!(scanner.tokens.len(), 13);
-//         assert_eq!(scanner.tokens[0].token_type, TokenType::Var);
-//         assert_eq!(scanner.tokens[1].token_type, TokenType::Identifier);
-//         assert_eq!(scanner.tokens[2].token_type, TokenType::Equal);
-//         assert_eq!(scanner.tokens[3].token_type, TokenType::Number);
-//         assert_eq!(scanner.tokens[4].token_type, TokenType::Semicolon);
-//         assert_eq!(scanner.tokens[5].token_type, TokenType::While);
-//         assert_eq!(scanner.tokens[6].token_type, TokenType::True);
-//         assert_eq!(scanner.tokens[7].token_type, TokenType::LeftBrace);
-//         assert_eq!(scanner.tokens[8].token_type, TokenType::Print);
-//         assert_eq!(scanner.tokens[9].token_type, TokenType::Number);
-//         assert_eq!(scanner.tokens[10].token_type, TokenType::RightBrace);
-//         assert_eq!(scanner.tokens[11].token_type, TokenType::Semicolon);
-//         assert_eq!(scanner.tokens[12].token_type, TokenType::Eof);
-//     }
-// }
\ No newline at end of file
+        for i in 0..3 {
+            assert_eq!(scanner.tokens[i].token_type, TokenType::Number)
+        }
+        match scanner.tokens[0].literal {
+            Some(LiteralValue::FloatValue(value)) => assert_eq!(value, 123.123),
+            _ => panic!("Incorrect literal value"),
+        }
+        match scanner.tokens[1].literal {
+            Some(LiteralValue::FloatValue(value)) => assert_eq!(value, 321.0),
+            _ => panic!("Incorrect literal value"),
+        }
+        match scanner.tokens[2].literal {
+            Some(LiteralValue::IntValue(value)) => assert_eq!(value, 5),
+            _ => panic!("Incorrect literal value"),
+        }
+    }
+
+    #[test]
+    fn get_identifier() {
+        let source = "this_is_a_var = 12;";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 5);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::Identifier);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Equal);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::Semicolon);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn get_keywords() {
+        let source = "var this_is_var = 12;\nwhile true { print 3 };";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 13);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::Var);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Identifier);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::Equal);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::Semicolon);
+        assert_eq!(scanner.tokens[5].token_type, TokenType::While);
+        assert_eq!(scanner.tokens[6].token_type, TokenType::True);
+        assert_eq!(scanner.tokens[7].token_type, TokenType::LeftBrace);
+        assert_eq!(scanner.tokens[8].token_type, TokenType::Print);
+        assert_eq!(scanner.tokens[9].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[10].token_type, TokenType::RightBrace);
+        assert_eq!(scanner.tokens[11].token_type, TokenType::Semicolon);
+        assert_eq!(scanner.tokens[12].token_type, TokenType::Eof);
+    }
+
+    fn string_value(source: &str) -> Result<String, String> {
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().map_err(|errors| errors[0].to_string())?;
+        match scanner.tokens[0].literal.as_ref().unwrap() {
+            LiteralValue::StringValue(value) => Ok(value.clone()),
+            _ => panic!("Incorrect literal value"),
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(string_value(r#""a\tb\n\"q\" \\ \0""#).unwrap(), "a\tb\n\"q\" \\ \0");
+        assert_eq!(string_value(r#""\u{48}\u{1F600}""#).unwrap(), "H\u{1F600}");
+    }
+
+    #[test]
+    fn malformed_escapes_report_line_and_column() {
+        let error = string_value("\n  \"ab\\q\"").unwrap_err();
+        assert!(error.starts_with("Line 2, column 6: Unknown escape sequence '\\q'"), "{}", error);
+        assert!(string_value(r#""\u{}""#).unwrap_err().contains("1 to 6 hex digits"));
+        assert!(string_value(r#""\u{D800}""#).unwrap_err().contains("not a valid Unicode code point"));
+        assert!(string_value(r#""\u0041""#).unwrap_err().contains("Expected '{'"));
+    }
+
+    #[test]
+    fn interpolation_segments() {
+        let mut scanner = Scanner::new(r#""a${ {"b": 1}["b"] }c${d}""#);
+        scanner.scan_tokens().unwrap();
+        let types: Vec<TokenType> = scanner.tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(types, [
+            TokenType::Interpolation, TokenType::LeftBrace, TokenType::String, TokenType::Colon,
+            TokenType::Number, TokenType::RightBrace, TokenType::LeftBracket, TokenType::String,
+            TokenType::RightBracket, TokenType::Interpolation, TokenType::Identifier,
+            TokenType::String, TokenType::Eof,
+        ]);
+        let errors = Scanner::new(r#""a${b"#).scan_tokens().unwrap_err();
+        assert!(matches!(errors[0], ScanError::UnterminatedInterpolation { .. }));
+    }
+
+    #[test]
+    fn unicode_identifiers_and_strings() {
+        let source = "var größe = \"日本語 🎉\"; _ñ1";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Identifier);
+        assert_eq!(scanner.tokens[1].lexeme, "größe");
+        assert_eq!(scanner.tokens[3].lexeme, "\"日本語 🎉\"");
+        assert_eq!(scanner.tokens[5].lexeme, "_ñ1");
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let error = string_value("\"日本\\q\"").unwrap_err();
+        assert!(error.starts_with("Line 1, column 4:"), "{}", error);
+    }
+
+    #[test]
+    fn never_panics_on_odd_input() {
+        let inputs = [
+            "€", "\"é", "\"\\", "\"\\u{", "\"\\u{1F6", "\"${", "\"${\"${", "}", "1.", "1..",
+            "\u{0}", "a\u{301}", "\"\\é\"", "\u{FEFF}print 1;", "\"${}\"", "// 日本",
+        ];
+        for input in inputs {
+            let _ = Scanner::new(input).scan_tokens();
+        }
+    }
+
+    #[test]
+    fn token_spans_track_lines_and_columns() {
+        let mut scanner = Scanner::new("var é =\n  \"a\nb\";");
+        scanner.scan_tokens().unwrap();
+        let name = scanner.tokens[1].span;
+        assert_eq!((name.start, name.end, name.line, name.column, name.end_column), (4, 6, 1, 5, 6));
+        let string = scanner.tokens[3].span;
+        assert_eq!((string.line, string.column, string.end_line, string.end_column), (2, 3, 3, 3));
+        let eof = scanner.tokens.last().unwrap().span;
+        assert_eq!((eof.line, eof.column), (3, 4));
+    }
+}
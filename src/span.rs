@@ -0,0 +1,64 @@
+use std::fmt;
+
+/// A stretch of source text. `start..end` are byte offsets into the source;
+/// lines and columns are 1-based, columns count characters, and
+/// `end_column` is one past the last character on `end_line`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        let first = if other.start < self.start { other } else { self };
+        let last = if other.end > self.end { other } else { self };
+        Span {
+            start: first.start,
+            line: first.line,
+            column: first.column,
+            end: last.end,
+            end_line: last.end_line,
+            end_column: last.end_column,
+        }
+    }
+
+    /// An empty span just past the end of `self`.
+    pub fn after(self) -> Span {
+        Span {
+            start: self.end,
+            end: self.end,
+            line: self.end_line,
+            column: self.end_column,
+            end_line: self.end_line,
+            end_column: self.end_column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}, column {}", self.line, self.column)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joined_spans_cover_both_ends() {
+        let a = Span { start: 4, end: 5, line: 1, column: 5, end_line: 1, end_column: 6 };
+        let b = Span { start: 10, end: 14, line: 2, column: 3, end_line: 2, end_column: 7 };
+        let joined = Span { start: 4, end: 14, line: 1, column: 5, end_line: 2, end_column: 7 };
+        assert_eq!(a.to(b), joined);
+        assert_eq!(b.to(a), joined);
+        assert_eq!(joined.to_string(), "Line 1, column 5");
+    }
+}
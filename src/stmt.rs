@@ -1,12 +1,17 @@
 use crate::ast::Expr;
 use crate::scanner::Token;
+use crate::span::Span;
+use std::fmt;
+use std::rc::Rc;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Stmt {
     Expression { expression: Expr },
-    Print { expression: Expr },
+    Print { keyword: Token, expression: Expr },
     Var { name: Token, initializer: Expr },
-    Block { statements: Vec<Box<Stmt>> },
+    /// `span` covers the braces.
+    Block { span: Span, statements: Vec<Stmt> },
     IfStmt { predicate: Expr, then: Box<Stmt>, els: Option<Box<Stmt>> },
     WhileStmt {
         condition: Expr,
@@ -20,29 +25,126 @@ pub enum Stmt {
         increment: Option<Expr>,
         body: Box<Stmt>,
     },
+    ForInStmt { variable: Token, iterable: Expr, body: Box<Stmt> },
+    Function {
+        name: Token,
+        params: Vec<Token>,
+        body: Rc<Vec<Stmt>>,
+    },
+    Return { keyword: Token, value: Option<Expr> },
+    Break { keyword: Token, label: Option<Token> },
+    Continue { keyword: Token, label: Option<Token> },
+    /// `label: while ...` or `label: for ...`; `body` is always a loop.
+    Labeled { label: Token, body: Box<Stmt> },
+    Class { name: Token, superclass: Option<Expr>, methods: Vec<Stmt> },
 }
 
 impl Stmt {
-    #[allow(dead_code)]
-    pub fn to_string(&self) -> String {
+    /// The source text this statement was parsed from, give or take the
+    /// leading keyword and trailing `;`.
+    pub fn span(&self) -> Span {
         match self {
-            Stmt::Expression { expression } => expression.to_string(),
-            Stmt::Print { expression } => format!("(print {})", expression.to_string()),
-            Stmt::Var { name, initializer: _ } => format!("(var {})", name.lexeme),
-            Stmt::Block { statements } => format!(
-                "(block {})",
-                statements.into_iter().map(|stmt| stmt.to_string())
-                .collect::<String>()
+            Stmt::Expression { expression } => expression.span(),
+            Stmt::Print { keyword, expression } => keyword.span.to(expression.span()),
+            Stmt::Var { name, initializer } => name.span.to(initializer.span()),
+            Stmt::Block { span, statements: _ } => *span,
+            Stmt::IfStmt { predicate, then, els } => {
+                let last = els.as_ref().unwrap_or(then);
+                predicate.span().to(last.span())
+            },
+            Stmt::WhileStmt { condition, body } => condition.span().to(body.span()),
+            Stmt::ForStmt { var_decl, expr_stmt, condition, increment, body } => {
+                let first = var_decl.as_ref().or(expr_stmt.as_ref()).map(|stmt| stmt.span())
+                    .or(condition.as_ref().map(Expr::span))
+                    .or(increment.as_ref().map(Expr::span))
+                    .unwrap_or(body.span());
+                first.to(body.span())
+            },
+            Stmt::ForInStmt { variable, iterable: _, body } => variable.span.to(body.span()),
+            Stmt::Function { name, params: _, body } => match body.last() {
+                Some(last) => name.span.to(last.span()),
+                None => name.span,
+            },
+            Stmt::Return { keyword, value } => match value {
+                Some(value) => keyword.span.to(value.span()),
+                None => keyword.span,
+            },
+            Stmt::Break { keyword, label } | Stmt::Continue { keyword, label } => match label {
+                Some(label) => keyword.span.to(label.span),
+                None => keyword.span,
+            },
+            Stmt::Labeled { label, body } => label.span.to(body.span()),
+            Stmt::Class { name, superclass: _, methods } => match methods.last() {
+                Some(last) => name.span.to(last.span()),
+                None => name.span,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Expression { expression } => write!(f, "{}", expression),
+            Stmt::Print { keyword: _, expression } => write!(f, "(print {})", expression),
+            Stmt::Var { name, initializer } => write!(f, "(var {} {})", name.lexeme, initializer),
+            Stmt::Block { span: _, statements } => write!(
+                f,
+                "(block{})",
+                statements.iter().map(|stmt| format!(" {}", stmt)).collect::<String>()
             ),
-            Stmt::IfStmt { predicate: _, then: _, els: _ } => todo!(),
-            Stmt::WhileStmt { condition: _, body: _ } => todo!(),
+            Stmt::IfStmt { predicate, then, els } => match els {
+                Some(els) => write!(f, "(if {} {} {})", predicate, then, els),
+                None => write!(f, "(if {} {})", predicate, then),
+            },
+            Stmt::WhileStmt { condition, body } => write!(f, "(while {} {})", condition, body),
             Stmt::ForStmt {
-                var_decl:_,
-                expr_stmt: _,
-                condition: _,
-                increment: _,
-                body: _ 
-            } => todo!()
+                var_decl,
+                expr_stmt,
+                condition,
+                increment,
+                body
+            } => {
+                let initializer = var_decl.as_ref().or(expr_stmt.as_ref());
+                write!(
+                    f,
+                    "(for {} {} {} {})",
+                    initializer.map_or("nil".to_string(), |stmt| stmt.to_string()),
+                    condition.as_ref().map_or("nil".to_string(), |expr| expr.to_string()),
+                    increment.as_ref().map_or("nil".to_string(), |expr| expr.to_string()),
+                    body
+                )
+            },
+            Stmt::ForInStmt { variable, iterable, body } => {
+                write!(f, "(for-in {} {} {})", variable.lexeme, iterable, body)
+            },
+            Stmt::Function { name, params, body } => write!(
+                f,
+                "(fun {} ({}){})",
+                name.lexeme,
+                params.iter().map(|param| param.lexeme.clone()).collect::<Vec<String>>().join(" "),
+                body.iter().map(|stmt| format!(" {}", stmt)).collect::<String>()
+            ),
+            Stmt::Return { keyword: _, value } => match value {
+                Some(value) => write!(f, "(return {})", value),
+                None => write!(f, "(return)"),
+            },
+            Stmt::Break { keyword: _, label } => match label {
+                Some(label) => write!(f, "(break {})", label.lexeme),
+                None => write!(f, "(break)"),
+            },
+            Stmt::Continue { keyword: _, label } => match label {
+                Some(label) => write!(f, "(continue {})", label.lexeme),
+                None => write!(f, "(continue)"),
+            },
+            Stmt::Labeled { label, body } => write!(f, "(label {} {})", label.lexeme, body),
+            Stmt::Class { name, superclass, methods } => write!(
+                f,
+                "(class {}{}{})",
+                name.lexeme,
+                superclass.as_ref().map_or("".to_string(), |superclass| format!(" < {}", superclass)),
+                methods.iter().map(|method| format!(" {}", method)).collect::<String>()
+            ),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,65 @@
+use crate::ast::LiteralValue;
+use crate::error::RuntimeError;
+use crate::natives::NativeFunction;
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// A Rust value handed to scripts as an opaque object, e.g. a database
+/// handle. Scripts can only use it through `.`: reading and assigning the
+/// properties it exposes and calling its methods. The host gets it back
+/// with `LiteralValue::downcast_ref` and `downcast_mut`.
+pub trait UserData: Any + fmt::Debug {
+    /// The name scripts see when the value is printed or misused.
+    fn type_name(&self) -> &str;
+
+    /// Reads the property `name`, or `None` if there is no such property.
+    fn get(&self, _name: &str) -> Option<LiteralValue> {
+        None
+    }
+
+    /// Assigns the property `name`. Properties are read-only unless this is
+    /// overridden.
+    fn set(&mut self, name: &str, _value: LiteralValue) -> Result<(), RuntimeError> {
+        Err(format!("Can't set property '{}' on {}", name, self.type_name()).into())
+    }
+
+    fn has_method(&self, _name: &str) -> bool {
+        false
+    }
+
+    /// Runs the method `name`, which `has_method` said exists. Methods check
+    /// their own arguments.
+    fn call_method(&mut self, name: &str, _arguments: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+        Err(format!("Undefined method '{}' on {}", name, self.type_name()).into())
+    }
+}
+
+/// `object.name`: a property if there is one, otherwise the method `name`
+/// bound to `object`.
+pub fn get_property(object: &Rc<RefCell<dyn UserData>>, name: &str) -> Result<LiteralValue, RuntimeError> {
+    let data = object.try_borrow().map_err(|_| in_use())?;
+    if let Some(value) = data.get(name) {
+        return Ok(value);
+    }
+    if !data.has_method(name) {
+        return Err(format!("Undefined property '{}' on {}", name, data.type_name()).into());
+    }
+    let receiver = object.clone();
+    let method = name.to_string();
+    let native = NativeFunction::new(name, None, move |arguments| {
+        receiver.try_borrow_mut().map_err(|_| in_use())?.call_method(&method, arguments)
+    });
+    Ok(native.into_value())
+}
+
+pub fn set_property(object: &Rc<RefCell<dyn UserData>>, name: &str, value: LiteralValue) -> Result<(), RuntimeError> {
+    object.try_borrow_mut().map_err(|_| in_use())?.set(name, value)
+}
+
+/// A method was handed the object it was called on, or the host is holding
+/// a borrow of it while a script runs.
+fn in_use() -> RuntimeError {
+    "Host object is already in use".to_string().into()
+}
@@ -0,0 +1,513 @@
+use crate::ast::LiteralValue;
+use crate::callable::Callable;
+use crate::chunk::{FunctionProto, OpCode};
+use crate::class::{LoxClass, LoxInstance};
+use crate::console::Console;
+use crate::error::RuntimeError;
+use crate::limits::{Budget, Limits};
+use crate::map::LoxMap;
+use crate::natives;
+use crate::scanner::TokenType;
+use crate::span::Span;
+use crate::userdata;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A captured variable. It points at a live stack slot until the slot goes
+/// out of scope, after which it owns the value.
+pub enum Upvalue {
+    Open(usize),
+    Closed(LiteralValue),
+}
+
+/// A compiled function together with the variables it captured.
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A method looked up on an instance, remembering the receiver for `this`.
+pub struct BoundMethod {
+    pub receiver: LoxInstance,
+    pub method: Rc<Closure>,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Index of the frame's slot zero on the value stack.
+    slots: usize,
+}
+
+/// Stack-based virtual machine executing the bytecode produced by `Compiler`.
+pub struct Vm {
+    stack: Vec<LiteralValue>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<String, LiteralValue>,
+    /// Upvalues still pointing into the stack, ordered by slot.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Offset of the instruction being run in the innermost frame, so a
+    /// runtime error can be traced back to its source span.
+    op_start: usize,
+    console: Console,
+    budget: Budget,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::with_console(Console::stdio())
+    }
+
+    pub fn with_console(console: Console) -> Self {
+        Self {
+            stack: Vec::with_capacity(256),
+            frames: Vec::with_capacity(64),
+            globals: natives::globals(&console).into_iter().collect(),
+            open_upvalues: vec![],
+            op_start: 0,
+            console,
+            budget: Budget::new(Limits::default()),
+        }
+    }
+
+    /// Applies to every later call to `interpret`; time counts from the
+    /// start of each one.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Budget::new(limits);
+    }
+
+    pub fn interpret(&mut self, function: Rc<FunctionProto>) -> Result<(), RuntimeError> {
+        let closure = Rc::new(Closure { function, upvalues: vec![] });
+        self.budget = Budget::new(self.budget.limits());
+        self.stack.push(LiteralValue::Callable(Callable::Closure(closure.clone())));
+        // Errors are raised without a span and located at the instruction that was running.
+        let result = self.call_closure(closure, 0).and_then(|_| self.run()).map_err(|error| {
+            match self.frames.last() {
+                Some(frame) => error.at(frame.closure.function.chunk.spans[self.op_start]),
+                None => error,
+            }
+        });
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn run(&mut self) -> Result<(), RuntimeError> {
+        let mut frame = self.frames.last().expect("frame to run").closure.clone();
+        let mut ip = self.frames.last().expect("frame to run").ip;
+        let mut slots = self.frames.last().expect("frame to run").slots;
+
+        loop {
+            self.op_start = ip;
+            self.budget.step()?;
+            let byte = frame.function.chunk.code[ip];
+            let op = match OpCode::from_byte(byte) {
+                Some(op) => op,
+                None => return Err(format!("Unknown opcode {}", byte).into()),
+            };
+            ip += 1;
+
+            match op {
+                OpCode::Constant => {
+                    let index = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    self.stack.push(frame.function.chunk.constants[index].clone());
+                },
+                OpCode::Nil => self.stack.push(LiteralValue::Nil),
+                OpCode::True => self.stack.push(LiteralValue::True),
+                OpCode::False => self.stack.push(LiteralValue::False),
+                OpCode::Pop => {
+                    self.pop();
+                },
+                OpCode::GetLocal => {
+                    let slot = frame.function.chunk.code[ip] as usize;
+                    ip += 1;
+                    self.stack.push(self.stack[slots + slot].clone());
+                },
+                OpCode::SetLocal => {
+                    let slot = frame.function.chunk.code[ip] as usize;
+                    ip += 1;
+                    self.stack[slots + slot] = self.peek(0).clone();
+                },
+                OpCode::GetGlobal => {
+                    let name = self.read_name(&frame, ip);
+                    ip += 2;
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(undefined_variable(name)),
+                    }
+                },
+                OpCode::DefineGlobal => {
+                    let name = self.read_name(&frame, ip).to_string();
+                    ip += 2;
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                },
+                OpCode::SetGlobal => {
+                    let name = self.read_name(&frame, ip);
+                    ip += 2;
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(undefined_variable(name)),
+                    }
+                },
+                OpCode::GetUpvalue => {
+                    let index = frame.function.chunk.code[ip] as usize;
+                    ip += 1;
+                    let value = match &*frame.upvalues[index].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::SetUpvalue => {
+                    let index = frame.function.chunk.code[ip] as usize;
+                    ip += 1;
+                    let value = self.peek(0).clone();
+                    let mut upvalue = frame.upvalues[index].borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                },
+                OpCode::GetProperty => {
+                    let name = self.read_name(&frame, ip);
+                    ip += 2;
+                    let value = match self.pop() {
+                        LiteralValue::Instance(instance) => instance.get(name)?,
+                        LiteralValue::UserData(data) => userdata::get_property(&data, name)?,
+                        other => return Err(format!(
+                            "Only instances have properties, got {} for '{}'", other.to_type(), name
+                        ).into()),
+                    };
+                    self.stack.push(value);
+                },
+                OpCode::SetProperty => {
+                    let name = self.read_name(&frame, ip);
+                    ip += 2;
+                    let value = self.pop();
+                    match self.pop() {
+                        LiteralValue::Instance(instance) => instance.set(name, value.clone()),
+                        LiteralValue::UserData(data) => userdata::set_property(&data, name, value.clone())?,
+                        other => return Err(format!(
+                            "Only instances have fields, got {} for '{}'", other.to_type(), name
+                        ).into()),
+                    }
+                    self.stack.push(value);
+                },
+                OpCode::GetSuper => {
+                    let name = self.read_name(&frame, ip);
+                    ip += 2;
+                    let superclass = match self.pop() {
+                        LiteralValue::Callable(Callable::Class(class)) => class,
+                        _ => return Err("Can't use 'super' in a class with no superclass".to_string().into()),
+                    };
+                    let instance = match self.pop() {
+                        LiteralValue::Instance(instance) => instance,
+                        _ => return Err("Can't use 'super' outside of a method".to_string().into()),
+                    };
+                    match superclass.find_method(name) {
+                        Some(method) => self.stack.push(LiteralValue::Callable(method.bind(instance))),
+                        None => return Err(format!(
+                            "Undefined property '{}' on superclass {}", name, superclass.name
+                        ).into()),
+                    }
+                },
+                OpCode::Equal => self.binary(TokenType::EqualEqual)?,
+                OpCode::NotEqual => self.binary(TokenType::BangEqual)?,
+                OpCode::Greater => self.binary(TokenType::Greater)?,
+                OpCode::GreaterEqual => self.binary(TokenType::GreaterEqual)?,
+                OpCode::Less => self.binary(TokenType::Less)?,
+                OpCode::LessEqual => self.binary(TokenType::LessEqual)?,
+                OpCode::Add => self.binary(TokenType::Plus)?,
+                OpCode::Subtract => self.binary(TokenType::Minus)?,
+                OpCode::Multiply => self.binary(TokenType::Star)?,
+                OpCode::Divide => self.binary(TokenType::Slash)?,
+                OpCode::Modulo => self.binary(TokenType::Percent)?,
+                OpCode::Range => self.binary(TokenType::DotDot)?,
+                OpCode::RangeInclusive => self.binary(TokenType::DotDotEqual)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(value.unary_op(TokenType::Bang)?);
+                },
+                OpCode::Negate => {
+                    let value = self.pop();
+                    self.stack.push(value.unary_op(TokenType::Minus)?);
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    self.console.print(&value.to_string())?;
+                },
+                OpCode::Jump => {
+                    let offset = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2 + offset;
+                },
+                OpCode::JumpIfFalse => {
+                    let offset = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    if self.peek(0).is_truthy() == LiteralValue::False {
+                        ip += offset;
+                    }
+                },
+                OpCode::Loop => {
+                    let offset = frame.function.chunk.read_u16(ip) as usize;
+                    ip = ip + 2 - offset;
+                },
+                OpCode::Call => {
+                    let arg_count = frame.function.chunk.code[ip] as usize;
+                    ip += 1;
+                    self.frames.last_mut().expect("calling frame").ip = ip;
+                    self.call_value(arg_count)?;
+                    let current = self.frames.last().expect("called frame");
+                    frame = current.closure.clone();
+                    ip = current.ip;
+                    slots = current.slots;
+                },
+                OpCode::Closure => {
+                    let index = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    let function = frame.function.chunk.functions[index].clone();
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = frame.function.chunk.code[ip] == 1;
+                        let index = frame.function.chunk.code[ip + 1] as usize;
+                        ip += 2;
+                        if is_local {
+                            upvalues.push(self.capture_upvalue(slots + index));
+                        } else {
+                            upvalues.push(frame.upvalues[index].clone());
+                        }
+                    }
+                    let closure = Closure { function, upvalues };
+                    self.stack.push(LiteralValue::Callable(Callable::Closure(Rc::new(closure))));
+                },
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                },
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.frames.pop();
+                    self.close_upvalues(slots);
+                    self.stack.truncate(slots);
+                    match self.frames.last() {
+                        None => return Ok(()),
+                        Some(current) => {
+                            self.stack.push(result);
+                            frame = current.closure.clone();
+                            ip = current.ip;
+                            slots = current.slots;
+                        },
+                    }
+                },
+                OpCode::Class => {
+                    let name = self.read_name(&frame, ip).to_string();
+                    let method_count = frame.function.chunk.code[ip + 2] as usize;
+                    let has_superclass = frame.function.chunk.code[ip + 3] == 1;
+                    ip += 4;
+                    self.class(name, method_count, has_superclass)?;
+                },
+                OpCode::BuildList => {
+                    let count = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(LiteralValue::list(elements));
+                },
+                OpCode::BuildMap => {
+                    let count = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    let mut map = LoxMap::default();
+                    let mut values = self.stack.split_off(self.stack.len() - 2 * count).into_iter();
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        map.insert(key, value)?;
+                    }
+                    self.stack.push(LiteralValue::Map(Rc::new(RefCell::new(map))));
+                },
+                OpCode::BuildString => {
+                    let count = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let text = parts.iter().map(LiteralValue::display).collect();
+                    self.stack.push(LiteralValue::StringValue(text));
+                },
+                OpCode::GetIter => {
+                    let iterator = self.pop().iterate()?;
+                    self.stack.push(LiteralValue::Iterator(iterator));
+                },
+                OpCode::ForIter => {
+                    let offset = frame.function.chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    let next = match self.peek(0) {
+                        LiteralValue::Iterator(iterator) => iterator.borrow_mut().next()?,
+                        other => return Err(format!("{} is not iterable", other.to_type()).into()),
+                    };
+                    match next {
+                        Some(value) => self.stack.push(value),
+                        None => ip += offset,
+                    }
+                },
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    self.stack.push(object.get_index(&index)?);
+                },
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    object.set_index(&index, value.clone())?;
+                    self.stack.push(value);
+                },
+            }
+        }
+    }
+
+    fn class(&mut self, name: String, method_count: usize, has_superclass: bool) -> Result<(), RuntimeError> {
+        let superclass = if has_superclass {
+            match self.pop() {
+                LiteralValue::Callable(Callable::Class(class)) => Some(class),
+                other => return Err(format!(
+                    "Superclass of '{}' must be a class, got {}", name, other.to_type()
+                ).into()),
+            }
+        } else {
+            None
+        };
+
+        let mut methods = HashMap::new();
+        for value in self.stack.drain(self.stack.len() - method_count..) {
+            if let LiteralValue::Callable(Callable::Closure(closure)) = value {
+                methods.insert(closure.function.name.clone(), Callable::Closure(closure));
+            }
+        }
+
+        let class = LoxClass { name, superclass, methods };
+        self.stack.push(LiteralValue::Callable(Callable::Class(Rc::new(class))));
+        Ok(())
+    }
+
+    fn call_value(&mut self, arg_count: usize) -> Result<(), RuntimeError> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        let callable = match &self.stack[callee_slot] {
+            LiteralValue::Callable(callable) => callable.clone(),
+            other => return Err(RuntimeError::NotCallable {
+                span: Span::default(),
+                type_name: other.to_type().to_string(),
+            }),
+        };
+
+        match callable {
+            Callable::Closure(closure) => self.call_closure(closure, arg_count),
+            Callable::BoundMethod(bound) => {
+                self.stack[callee_slot] = LiteralValue::Instance(bound.receiver.clone());
+                self.call_closure(bound.method.clone(), arg_count)
+            },
+            Callable::Class(class) => {
+                self.stack[callee_slot] = LiteralValue::Instance(LoxInstance::new(class.clone()));
+                match class.find_method("init") {
+                    Some(Callable::Closure(initializer)) => self.call_closure(initializer, arg_count),
+                    Some(_) => Err(format!("Initializer of {} was not compiled to bytecode", class.name).into()),
+                    None if arg_count != 0 => Err(wrong_arity(&class.name, 0, arg_count)),
+                    None => Ok(()),
+                }
+            },
+            Callable::Native(native) => {
+                if let Some(arity) = native.arity.filter(|arity| *arity != arg_count) {
+                    return Err(wrong_arity(&native.name, arity, arg_count));
+                }
+                let arguments = self.stack.split_off(callee_slot + 1);
+                let result = (native.function)(&arguments)?;
+                self.stack.pop();
+                self.stack.push(result);
+                Ok(())
+            },
+            Callable::Function(function) => Err(format!(
+                "Function '{}' was not compiled to bytecode", function.name.lexeme
+            ).into()),
+        }
+    }
+
+    fn call_closure(&mut self, closure: Rc<Closure>, arg_count: usize) -> Result<(), RuntimeError> {
+        if arg_count != closure.function.arity {
+            return Err(wrong_arity(&closure.function.name, closure.function.arity, arg_count));
+        }
+        self.budget.enter(self.frames.len())?;
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self.open_upvalues.iter().find(|upvalue| {
+            matches!(&*upvalue.borrow(), Upvalue::Open(open) if *open == slot)
+        });
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        let position = self.open_upvalues.iter()
+            .position(|other| matches!(&*other.borrow(), Upvalue::Open(open) if *open > slot))
+            .unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(position, upvalue.clone());
+        upvalue
+    }
+
+    /// Moves every captured stack slot at or above `last` into its upvalue.
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) if *slot >= last => *slot,
+                _ => break,
+            };
+            let upvalue = self.open_upvalues.pop().expect("open upvalue");
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+        }
+    }
+
+    fn binary(&mut self, operator: TokenType) -> Result<(), RuntimeError> {
+        let right = self.pop();
+        let left = self.pop();
+        self.stack.push(left.binary_op(operator, &right)?);
+        Ok(())
+    }
+
+    fn read_name<'a>(&self, frame: &'a Closure, ip: usize) -> &'a str {
+        let index = frame.function.chunk.read_u16(ip) as usize;
+        match &frame.function.chunk.constants[index] {
+            LiteralValue::StringValue(name) => name,
+            _ => "",
+        }
+    }
+
+    /// Panics if the stack is empty: compiled code always pushes before it
+    /// pops, so that would be a bug in the compiler.
+    fn pop(&mut self) -> LiteralValue {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &LiteralValue {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn undefined_variable(name: &str) -> RuntimeError {
+    RuntimeError::UndefinedVariable { span: Span::default(), name: name.to_string() }
+}
+
+fn wrong_arity(callee: &str, expected: usize, got: usize) -> RuntimeError {
+    RuntimeError::WrongArity { span: Span::default(), callee: callee.to_string(), expected, got }
+}
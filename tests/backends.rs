@@ -0,0 +1,94 @@
+//! Runs every script in `tests/samples` on both the tree-walking interpreter
+//! and the bytecode VM and checks that they print exactly the same thing.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn run(path: &Path, use_vm: bool) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rprt"));
+    if use_vm {
+        command.arg("--vm");
+    }
+    command.arg(path).output().expect("failed to run rprt")
+}
+
+fn run_script(path: &Path, use_vm: bool) -> String {
+    String::from_utf8_lossy(&run(path, use_vm).stdout).into_owned()
+}
+
+fn sample_scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("samples");
+    let mut scripts: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("samples directory")
+        .map(|entry| entry.expect("directory entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+#[test]
+fn backends_agree_on_every_sample() {
+    let scripts = sample_scripts();
+    assert!(!scripts.is_empty());
+
+    let mut mismatches = vec![];
+    for script in &scripts {
+        let tree_walker = run_script(script, false);
+        let vm = run_script(script, true);
+        if tree_walker != vm {
+            mismatches.push(format!(
+                "{}\n--- tree-walker ---\n{}--- vm ---\n{}",
+                script.display(),
+                tree_walker,
+                vm
+            ));
+        }
+    }
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+#[test]
+fn error_samples_report_errors() {
+    for script in sample_scripts() {
+        let name = script.file_stem().unwrap().to_string_lossy().into_owned();
+        let output = run_script(&script, false);
+        assert_eq!(
+            name.ends_with("error"),
+            output.contains("ERROR"),
+            "unexpected result for {}:\n{}",
+            name,
+            output
+        );
+    }
+}
+
+#[test]
+fn exit_status_tells_compile_errors_from_runtime_errors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("samples");
+    for (script, status) in [("compile_error.lox", 65), ("runtime_error.lox", 70), ("arithmetic.lox", 0)] {
+        for use_vm in [false, true] {
+            let code = run(&dir.join(script), use_vm).status.code();
+            assert_eq!(code, Some(status), "{} (vm: {})", script, use_vm);
+        }
+    }
+}
+
+#[test]
+fn self_referencing_containers_print_and_compare() {
+    let script = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("samples").join("cycles.lox");
+    for use_vm in [false, true] {
+        let output = run(&script, use_vm);
+        assert!(output.status.success(), "vm: {}\n{}", use_vm, String::from_utf8_lossy(&output.stderr));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            [
+                "[[...]]", "[1, [[...]]]", "true", "true", "true", "true", "false",
+                "{\"x\": {...}}", "{\"x\": [{...}]}", "true", "false", "",
+            ].join("\n"),
+            "vm: {}",
+            use_vm
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Embeds the interpreter through the library API, the way a host program
+//! would.
+
+use rprt::compiler::Compiler;
+use rprt::parser::Parser;
+use rprt::scanner::Scanner;
+use rprt::vm::Vm;
+use rprt::{Console, Engine, FromValue, IntoValue, Limits, LoxError, RuntimeError, UserData, Value};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+#[test]
+fn eval_returns_the_last_expression() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("var x = 2; x * 21;").unwrap(), Value::Int(42));
+    assert_eq!(engine.eval("var y = 1;").unwrap(), Value::Nil);
+    assert_eq!(engine.eval("").unwrap(), Value::Nil);
+}
+
+#[test]
+fn globals_persist_between_evals_and_are_visible_to_the_host() {
+    let mut engine = Engine::new();
+    engine.set_global("name", Value::StringValue("lox".to_string()));
+    engine.eval("fun greet() { return \"hi ${name}\"; } var greeting = greet();").unwrap();
+    assert_eq!(engine.get_global("greeting"), Some(Value::StringValue("hi lox".to_string())));
+    assert_eq!(engine.eval("greet();").unwrap(), Value::StringValue("hi lox".to_string()));
+    assert_eq!(engine.get_global("missing"), None);
+}
+
+#[test]
+fn errors_are_typed_by_phase() {
+    let mut engine = Engine::new();
+    assert!(matches!(engine.eval("var = 1;"), Err(LoxError::Parse(_))));
+    assert!(matches!(engine.eval("return 1;"), Err(LoxError::Compile(_))));
+    let error = engine.eval("1 + nil;").unwrap_err();
+    assert!(matches!(error, LoxError::Runtime(_)));
+    assert_eq!(error.exit_code(), 70);
+}
+
+#[test]
+fn scripts_call_registered_host_functions() {
+    let mut engine = Engine::new();
+    engine.register_fn("add", |a: i64, b: i64| a + b);
+    engine.register_fn("shout", |s: String, times: Option<i64>| s.to_uppercase().repeat(times.unwrap_or(1) as usize));
+    engine.register_fn("mean", |xs: Vec<f64>| {
+        if xs.is_empty() {
+            return Err(RuntimeError::from("mean() of an empty list".to_string()));
+        }
+        Ok(xs.iter().sum::<f64>() / xs.len() as f64)
+    });
+    engine.register_native("count", None, |arguments| Ok(Value::Int(arguments.len() as i64)));
+
+    assert_eq!(engine.eval("add(40, 2);").unwrap(), Value::Int(42));
+    assert_eq!(engine.eval("shout(\"hi\", nil) + shout(\"!\", 3);").unwrap(), "HI!!!".into_value());
+    assert_eq!(engine.eval("mean([1, 2.5, 3]);").unwrap(), Value::Float(6.5 / 3.0));
+    assert_eq!(engine.eval("count() + count(1, 2, 3);").unwrap(), Value::Int(3));
+
+    let error = engine.eval("add(1, \"2\");").unwrap_err();
+    assert_eq!(error.to_string(), "Line 1, column 11: Argument 2 of 'add': Expected Int, got String");
+    let error = engine.eval("\n  mean([]);").unwrap_err();
+    assert_eq!(error.to_string(), "Line 2, column 10: mean() of an empty list");
+    let error = engine.eval("add(1);").unwrap_err();
+    assert!(matches!(error, LoxError::Runtime(RuntimeError::WrongArity { expected: 2, got: 1, .. })));
+}
+
+#[derive(Debug)]
+struct Counter {
+    count: i64,
+}
+
+impl UserData for Counter {
+    fn type_name(&self) -> &str {
+        "Counter"
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        (name == "count").then(|| self.count.into_value())
+    }
+
+    fn set(&mut self, name: &str, value: Value) -> Result<(), RuntimeError> {
+        if name != "count" {
+            return Err(format!("Counter has no property '{}'", name).into());
+        }
+        self.count = i64::from_value(&value)?;
+        Ok(())
+    }
+
+    fn has_method(&self, name: &str) -> bool {
+        name == "add"
+    }
+
+    fn call_method(&mut self, _name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        for argument in arguments {
+            self.count += i64::from_value(argument)?;
+        }
+        Ok(self.count.into_value())
+    }
+}
+
+#[test]
+fn host_objects_expose_properties_and_methods() {
+    let mut engine = Engine::new();
+    engine.set_global("counter", Value::userdata(Counter { count: 1 }));
+
+    assert_eq!(engine.eval("counter.add(2, 3);").unwrap(), Value::Int(6));
+    assert_eq!(engine.eval("var add = counter.add; add(4); counter.count;").unwrap(), Value::Int(10));
+    engine.eval("counter.count = counter.count * 2;").unwrap();
+    assert_eq!(engine.eval("\"${counter}\";").unwrap(), "<Counter>".into_value());
+
+    let counter = engine.get_global("counter").unwrap();
+    assert_eq!(counter.downcast_ref::<Counter>().unwrap().count, 20);
+    counter.downcast_mut::<Counter>().unwrap().count = 0;
+    assert_eq!(engine.eval("counter.count;").unwrap(), Value::Int(0));
+    assert!(Value::Int(1).downcast_ref::<Counter>().is_none());
+
+    let error = engine.eval("counter.missing;").unwrap_err();
+    assert_eq!(error.to_string(), "Line 1, column 9: Undefined property 'missing' on Counter");
+    let error = engine.eval("counter.total = 1;").unwrap_err();
+    assert_eq!(error.to_string(), "Line 1, column 9: Counter has no property 'total'");
+    let error = engine.eval("counter.add(counter);").unwrap_err();
+    assert_eq!(error.to_string(), "Line 1, column 20: Expected Int, got UserData");
+}
+
+#[test]
+fn scripts_print_to_and_read_from_the_hosts_console() {
+    let lines = Rc::new(RefCell::new(vec![]));
+    let printed = lines.clone();
+    let console = Console::stdio()
+        .with_output_fn(move |line| printed.borrow_mut().push(line.to_string()))
+        .with_input("ada\r\nlovelace".as_bytes());
+    let mut engine = Engine::with_console(console);
+
+    engine.eval("var first = input(); print \"hello ${first}\"; print input(); print input();").unwrap();
+    assert_eq!(*lines.borrow(), ["\"hello ada\"", "\"lovelace\"", "nill"]);
+}
+
+#[test]
+fn print_writes_to_any_writer() {
+    let buffer = SharedBuffer::default();
+    let mut engine = Engine::with_console(Console::stdio().with_output(buffer.clone()));
+    engine.eval("print [1, 2]; print nil;").unwrap();
+    assert_eq!(String::from_utf8(buffer.0.borrow().clone()).unwrap(), "[1, 2]\nnill\n");
+}
+
+#[test]
+fn redirecting_a_cloned_console_leaves_the_original_alone() {
+    let (original, redirected) = (SharedBuffer::default(), SharedBuffer::default());
+    let console = Console::stdio().with_output(original.clone()).with_input("first\n".as_bytes());
+    let mut engine = Engine::with_console(console.clone());
+    let clone = console.with_output(redirected.clone()).with_input("second\n".as_bytes());
+
+    clone.print("from the clone").unwrap();
+    engine.eval("print input();").unwrap();
+    assert_eq!(String::from_utf8(original.0.borrow().clone()).unwrap(), "\"first\"\n");
+    assert_eq!(String::from_utf8(redirected.0.borrow().clone()).unwrap(), "from the clone\n");
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn limited(limits: Limits) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_limits(limits);
+    engine
+}
+
+fn run_on_vm(source: &str, limits: Limits) -> Result<(), RuntimeError> {
+    let mut scanner = Scanner::new(source);
+    scanner.scan_tokens().unwrap();
+    let stmts = Parser::new(scanner.tokens).parse().unwrap();
+    let function = Compiler::new().compile(&stmts).unwrap();
+    let mut vm = Vm::new();
+    vm.set_limits(limits);
+    vm.interpret(function)
+}
+
+#[test]
+fn runaway_scripts_stop_with_a_limit_error() {
+    let steps = Limits { max_steps: Some(1000), ..Limits::default() };
+    let error = limited(steps).eval("var i = 0;\nwhile (true) { i = i + 1; }").unwrap_err();
+    assert!(matches!(error, LoxError::Runtime(RuntimeError::StepLimitExceeded { limit: 1000, .. })));
+    assert_eq!(error.to_string(), "Line 2, column 14: Step limit of 1000 exceeded");
+    let error = run_on_vm("while (true) {}", steps).unwrap_err();
+    assert!(matches!(error, RuntimeError::StepLimitExceeded { limit: 1000, .. }));
+
+    let time = Limits { max_duration: Some(Duration::from_millis(20)), ..Limits::default() };
+    let error = limited(time).eval("for (;;) {}").unwrap_err();
+    assert!(matches!(error, LoxError::Runtime(RuntimeError::TimeLimitExceeded { .. })));
+    let error = run_on_vm("for (;;) {}", time).unwrap_err();
+    assert!(matches!(error, RuntimeError::TimeLimitExceeded { .. }));
+
+    let recursive = "fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; }";
+    let mut engine = limited(Limits { max_depth: 100, ..Limits::default() });
+    engine.eval(recursive).unwrap();
+    assert_eq!(engine.eval("f(99);").unwrap(), Value::Int(99));
+    let error = engine.eval("f(100);").unwrap_err();
+    assert_eq!(error.to_string(), "Line 1, column 48: Stack overflow: more than 100 nested calls");
+    let error = run_on_vm(&format!("{} f(1000);", recursive), Limits { max_depth: 100, ..Limits::default() });
+    assert!(matches!(error, Err(RuntimeError::StackOverflow { limit: 100, .. })));
+}
+
+#[test]
+fn deep_recursion_within_the_limit_does_not_overflow_the_stack() {
+    let mut engine = Engine::new();
+    engine.eval("fun f(n) { if (n == 0) return 0; { { return f(n - 1) + 1; } } }").unwrap();
+    assert_eq!(engine.eval("f(1000);").unwrap(), Value::Int(1000));
+    assert!(matches!(engine.eval("f(1025);"), Err(LoxError::Runtime(RuntimeError::StackOverflow { .. }))));
+}
+
+#[test]
+fn long_operator_chains_are_not_nesting() {
+    let sum = vec!["1"; 20_000].join(" + ");
+    assert_eq!(Engine::new().eval(&format!("{};", sum)).unwrap(), Value::Int(20_000));
+    assert!(run_on_vm(&format!("var x = {};", sum), Limits::default()).is_ok());
+    assert!(run_on_vm(&format!("var x = {} + nil;", sum), Limits::default()).is_err());
+
+    let parens = format!("{}1{};", "(".repeat(1001), ")".repeat(1001));
+    let error = Engine::new().eval(&parens).unwrap_err();
+    assert_eq!(error.diagnostics()[0].code, "E0104");
+}